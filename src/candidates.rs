@@ -0,0 +1,82 @@
+use std::str::FromStr;
+
+use anyhow::Context;
+use clap::Subcommand;
+use rusqlite::Connection;
+
+// A line as the translator logs it: scriptid:address, the address in hex
+#[derive(Clone, Copy, Debug)]
+pub struct Line {
+    scriptid: u16,
+    address: u32
+}
+
+impl FromStr for Line {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (scriptid, address) = s.split_once(':').context("expected SCRIPTID:ADDRESS")?;
+        Ok(Self {
+            scriptid: scriptid.parse()?,
+            address: u32::from_str_radix(address, 16)?
+        })
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    #[command(about = "List the stored candidates for a line; * marks the one in use")]
+    List {
+        #[arg(help = "SCRIPTID:ADDRESS, address in hex")]
+        line: Line
+    },
+    #[command(about = "Use a stored candidate as a line's translation")]
+    Pick {
+        #[arg(help = "SCRIPTID:ADDRESS, address in hex")]
+        line: Line,
+        index: u32
+    }
+}
+
+pub fn run(db: &mut Connection, command: Command) -> anyhow::Result<()> {
+    match command {
+        Command::List { line } => {
+            let mut stmt = db.prepare("
+                SELECT idx, c.tl_body, score, strategy, c.tl_body IS t.tl_body
+                FROM dialogueTlCandidates c LEFT JOIN dialogueTl t USING (scriptid, address)
+                WHERE scriptid = ? AND address = ?
+                ORDER BY idx")?;
+            let mut rows = stmt.query((line.scriptid, line.address))?;
+            let mut any = false;
+            while let Some(row) = rows.next()? {
+                let (idx, body, score, strategy, current) = <(u32, String, Option<f64>, String, bool)>::try_from(row)?;
+                let mark = if current { '*' } else { ' ' };
+                let score = score.map_or("-".into(), |s| format!("{s:.3}"));
+                println!("{mark}{idx}\t{score}\t{strategy}\t{body}");
+                any = true;
+            }
+            anyhow::ensure!(any, "no candidates for {}:{:X}", line.scriptid, line.address);
+        },
+        Command::Pick { line, index } => {
+            let tx = db.transaction()?;
+            let changed = tx.execute("
+                UPDATE dialogueTl SET tl_body = c.tl_body, reviewed = 0
+                FROM dialogueTlCandidates c
+                WHERE (dialogueTl.scriptid, dialogueTl.address, c.idx) = (?1, ?2, ?3)
+                    AND (c.scriptid, c.address) = (?1, ?2)",
+                (line.scriptid, line.address, index))?;
+            anyhow::ensure!(changed > 0, "no candidate {index} for {}:{:X}", line.scriptid, line.address);
+
+            // the line's metadata describes whichever candidate is in use
+            tx.execute("
+                UPDATE dialogueTlMeta SET strategy = c.strategy, n_predict = c.n_predict, pieces = c.pieces, params = c.params, logprob = c.logprob
+                FROM dialogueTlCandidates c
+                WHERE (dialogueTlMeta.scriptid, dialogueTlMeta.address, c.idx) = (?1, ?2, ?3)
+                    AND (c.scriptid, c.address) = (?1, ?2)",
+                (line.scriptid, line.address, index))?;
+            tx.commit()?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,435 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context as _;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub database: Option<PathBuf>,
+    pub backend: Backend,
+    pub server: Server,
+    pub openai: OpenAi,
+    pub retry: Retry,
+    pub context: Context,
+    pub prompt: Prompt,
+    pub recovery: Recovery,
+    pub runaway: Runaway,
+    pub grammar: Grammar,
+    pub candidates: Candidates,
+    pub memory: Memory,
+    pub examples: Examples,
+    pub sampling: Sampling,
+    pub placeholders: IndexMap<String, Placeholder>,
+    pub normalize: Vec<Rule>,
+    pub preflight: Preflight
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+pub enum Backend {
+    #[default]
+    #[serde(rename = "llama.cpp")]
+    LlamaCpp,
+    // any OpenAI-compatible chat completions server, see [openai]
+    #[serde(rename = "openai")]
+    OpenAi,
+    // prompt on the terminal for every line
+    #[serde(rename = "manual")]
+    Manual
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Server {
+    pub endpoint: String,
+    // show completions as they are generated, and allow cutting them short
+    pub stream: bool,
+    // let the server reuse the part of each prompt it has already evaluated
+    pub cache_prompt: bool,
+    // how many slots the server runs (llama-server -np), and so how many series run at once
+    pub slots: usize
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OpenAi {
+    // base URL, without /chat/completions
+    pub endpoint: String,
+    pub model: Option<String>,
+    // name of the environment variable holding the API key, if the server wants one
+    pub api_key_env: Option<String>,
+    pub system_prompt: String,
+    // there is no portable tokenizer endpoint, so history is capped by entry count instead
+    pub max_history: usize
+}
+
+// Backoff for transient server errors (connection failures, timeouts, 429, 5xx)
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Retry {
+    pub max_retries: u32,
+    pub initial_delay_ms: u64,
+    pub max_delay_ms: u64,
+    pub multiplier: f64,
+    // per attempt; unset waits forever
+    pub timeout_secs: Option<u64>
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Context {
+    pub n_ctx: usize,
+    pub n_predict: usize,
+    // share of the room for history to keep when some has to be dropped
+    pub keep: f64,
+    // tokenizer.json or GGUF model to count tokens with, instead of asking the server
+    pub tokenizer: Option<PathBuf>
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Prompt {
+    // a built-in template name, or a path to a .toml template
+    pub template: String
+}
+
+// What to do when a line runs out of output budget
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Recovery {
+    // larger budgets to try, in order
+    pub n_predict: Vec<usize>,
+    // then translate the line sentence by sentence
+    pub split: bool
+}
+
+// Generations to give up on early, handled like running out of tokens
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Runaway {
+    // the output ends in the same text this many times over; 0 disables
+    pub repeats: usize,
+    // ...covering at least this many characters
+    pub min_span: usize,
+    // kana or kanji in the output
+    pub japanese: bool
+}
+
+// What the llama.cpp grammar holds the output to, beyond the speaker prefix
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Grammar {
+    // quotes or parentheses wherever the line opens or closes with 「」 or （）
+    pub brackets: bool,
+    // no kana or kanji
+    pub japanese: bool,
+    // each placeholder's target as often as the line has its source
+    pub placeholders: bool
+}
+
+// Translate each line several times and keep the best, by `scorer`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Candidates {
+    pub n: usize,
+    pub scorer: Scorer,
+    // expected English characters per Japanese character, for the length scorer
+    pub length_ratio: f64
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scorer {
+    // highest mean token log probability
+    #[default]
+    Logprob,
+    // most glossary terms in the line rendered as their target
+    Glossary,
+    // length closest to `length_ratio` times the Japanese
+    Length
+}
+
+// Lines translated before, word for word
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Memory {
+    pub mode: MemoryMode,
+    // besides reviewed translations, trust the model's own with at least this mean token log
+    // probability
+    pub min_logprob: Option<f64>
+}
+
+// Similar translated lines shown in the metadata
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Examples {
+    // at most this many; 0 disables
+    pub n: usize,
+    // Dice similarity of the character bigrams, 0 to 1
+    pub min_similarity: f64,
+    // llama.cpp only: at most this many tokens of them
+    pub max_tokens: usize
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MemoryMode {
+    #[default]
+    Off,
+    // show the model the earlier translation
+    Hint,
+    // take the earlier translation as is
+    Reuse
+}
+
+// An engine control code, e.g. the player's name. `source` stands in for it in the Japanese the
+// model reads; `target` is how the model will render that in English, mapped back on output.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Placeholder {
+    pub source: String,
+    pub target: String
+}
+
+// Fix-ups for script artifacts, applied in order before placeholders are substituted.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Rule {
+    pub name: Option<String>,
+    #[serde(default)]
+    pub field: Field,
+    #[serde(flatten)]
+    pub kind: RuleKind
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Field {
+    Speaker,
+    Body,
+    #[default]
+    Both
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum RuleKind {
+    // the whole field is `from`
+    Exact { from: String, to: String },
+    // `to` may refer to capture groups as $1, ${name}
+    Regex { from: String, to: String },
+    Unicode { form: UnicodeForm }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UnicodeForm {
+    Nfc,
+    Nfd,
+    Nfkc,
+    Nfkd
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Preflight {
+    // refuse to translate while any speaker is unresolved
+    pub strict: bool
+}
+
+// Anything left unset is up to the server.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Sampling {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_p: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repeat_penalty: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<u32>
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            database: None,
+            backend: Backend::default(),
+            server: Server::default(),
+            openai: OpenAi::default(),
+            retry: Retry::default(),
+            context: Context::default(),
+            prompt: Prompt::default(),
+            recovery: Recovery::default(),
+            runaway: Runaway::default(),
+            grammar: Grammar::default(),
+            candidates: Candidates::default(),
+            memory: Memory::default(),
+            examples: Examples::default(),
+            sampling: Sampling::default(),
+            placeholders: [
+                ("#Name[1]", "玻ヰ璃", "Hairi"),
+                ("#Name[2]", "ハイリ", "Hairi")
+            ].into_iter().map(|(code, source, target)| {
+                (code.to_owned(), Placeholder { source: source.to_owned(), target: target.to_owned() })
+            }).collect(),
+            normalize: vec![
+                Rule {
+                    name: Some("trim".into()),
+                    field: Field::Speaker,
+                    kind: RuleKind::Regex { from: r"^\s+|\s+$".into(), to: String::new() }
+                },
+                // fix a stupid artifact
+                Rule {
+                    name: None,
+                    field: Field::Speaker,
+                    kind: RuleKind::Exact { from: "憂漣[ユーレン]ミュラー".into(), to: "憂漣[ユーレン]＝ミュラー".into() }
+                },
+                Rule {
+                    name: None,
+                    field: Field::Speaker,
+                    kind: RuleKind::Exact { from: "憂漣[ユーレン]=ミュラー".into(), to: "憂漣[ユーレン]＝ミュラー".into() }
+                }
+            ],
+            preflight: Preflight::default()
+        }
+    }
+}
+
+impl Default for Server {
+    fn default() -> Self {
+        Self { endpoint: "http://127.0.0.1:8080".into(), stream: false, cache_prompt: true, slots: 1 }
+    }
+}
+
+impl Default for OpenAi {
+    fn default() -> Self {
+        Self {
+            endpoint: "http://127.0.0.1:8080/v1".into(),
+            model: None,
+            api_key_env: None,
+            system_prompt: "Translate the user's lines of Japanese dialogue into natural English, \
+                one line per reply, keeping the [Speaker]: prefix in English. Metadata:".into(),
+            max_history: 32
+        }
+    }
+}
+
+impl Default for Retry {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            initial_delay_ms: 500,
+            max_delay_ms: 30_000,
+            multiplier: 2.0,
+            timeout_secs: None
+        }
+    }
+}
+
+impl Default for Context {
+    fn default() -> Self {
+        Self { n_ctx: 1024, n_predict: 64, keep: 0.5, tokenizer: None }
+    }
+}
+
+impl Default for Prompt {
+    fn default() -> Self {
+        Self { template: "llama3".into() }
+    }
+}
+
+impl Default for Recovery {
+    fn default() -> Self {
+        Self { n_predict: vec![128, 256], split: true }
+    }
+}
+
+impl Default for Runaway {
+    fn default() -> Self {
+        Self { repeats: 8, min_span: 24, japanese: true }
+    }
+}
+
+impl Default for Grammar {
+    fn default() -> Self {
+        Self { brackets: true, japanese: true, placeholders: true }
+    }
+}
+
+impl Default for Examples {
+    fn default() -> Self {
+        Self { n: 0, min_similarity: 0.4, max_tokens: 256 }
+    }
+}
+
+impl Default for Candidates {
+    fn default() -> Self {
+        Self { n: 1, scorer: Scorer::default(), length_ratio: 2.5 }
+    }
+}
+
+impl Config {
+    /// Loads the project file (if any) and applies `key=value` overrides on top of it.
+    /// Keys are dotted paths into the TOML document, e.g. `server.endpoint` or `sampling.seed`.
+    pub fn load(path: Option<&Path>, overrides: &[String]) -> anyhow::Result<Self> {
+        let mut table = match path {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                text.parse::<toml::Table>()
+                    .with_context(|| format!("failed to parse {}", path.display()))?
+            },
+            None => toml::Table::new()
+        };
+
+        for o in overrides {
+            let (key, value) = o.split_once('=').with_context(|| format!("override {o:?} is not key=value"))?;
+            set(&mut table, key.trim(), parse_value(value.trim()))?;
+        }
+
+        let mut config: Config = table.try_into().context("invalid configuration")?;
+
+        // relative paths are relative to the project file, not the working directory
+        if let Some(dir) = path.and_then(Path::parent) {
+            for p in [&mut config.database, &mut config.context.tokenizer].into_iter().flatten() {
+                if p.is_relative() {
+                    *p = dir.join(&*p);
+                }
+            }
+            let template = Path::new(&config.prompt.template);
+            if config.prompt.template.ends_with(".toml") && template.is_relative() {
+                config.prompt.template = dir.join(template).to_string_lossy().into_owned();
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse_value(value: &str) -> toml::Value {
+    // bare words are taken as strings so that `--set server.endpoint=http://...` works unquoted
+    format!("v = {value}").parse::<toml::Table>().ok()
+        .and_then(|mut t| t.remove("v"))
+        .unwrap_or_else(|| toml::Value::String(value.to_owned()))
+}
+
+fn set(table: &mut toml::Table, key: &str, value: toml::Value) -> anyhow::Result<()> {
+    match key.split_once('.') {
+        Some((head, rest)) => {
+            let sub = table.entry(head)
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                .as_table_mut().with_context(|| format!("{head} is not a table"))?;
+            set(sub, rest, value)
+        },
+        None => {
+            table.insert(key.to_owned(), value);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::path::Path;
+
+use rusqlite::{Connection, OpenFlags};
+
+// Applied in order; `user_version` records how many have run. Never edit one that has shipped.
+const MIGRATIONS: &[&str] = &[
+    "
+    CREATE TABLE IF NOT EXISTS dialogueTl (
+        scriptid INTEGER,
+        address INTEGER,
+        tl_body TEXT NOT NULL,
+        tl_variant_body TEXT,
+        PRIMARY KEY (scriptid, address),
+        FOREIGN KEY (scriptid, address) REFERENCES dialogue)
+    WITHOUT ROWID, STRICT;
+    ",
+    concat!("
+    CREATE TABLE characters (
+        jpspeaker TEXT PRIMARY KEY,
+        jpshort TEXT NOT NULL DEFAULT '',
+        enspeaker TEXT NOT NULL,
+        gender TEXT NOT NULL DEFAULT 'Unknown',
+        aliases TEXT NOT NULL DEFAULT '[]' CHECK (json_valid(aliases)))
+    STRICT;
+    ", include_str!("db/characters.sql")),
+    concat!("
+    CREATE TABLE glossary (
+        source TEXT PRIMARY KEY,
+        target TEXT NOT NULL,
+        type TEXT NOT NULL,
+        notes TEXT,
+        enabled INTEGER NOT NULL DEFAULT 1 CHECK (enabled IN (0, 1)))
+    STRICT;
+    ", include_str!("db/glossary.sql")),
+    "
+    CREATE TABLE dialogueTlMeta (
+        scriptid INTEGER,
+        address INTEGER,
+        strategy TEXT NOT NULL CHECK (strategy IN ('direct', 'budget', 'split')),
+        n_predict INTEGER,
+        pieces INTEGER,
+        variant_strategy TEXT CHECK (variant_strategy IN ('direct', 'budget', 'split')),
+        PRIMARY KEY (scriptid, address),
+        FOREIGN KEY (scriptid, address) REFERENCES dialogueTl ON DELETE CASCADE)
+    WITHOUT ROWID, STRICT;
+    ",
+    "
+    ALTER TABLE dialogueTlMeta ADD COLUMN params TEXT CHECK (params IS NULL OR json_valid(params));
+    ALTER TABLE dialogueTlMeta ADD COLUMN variant_params TEXT CHECK (variant_params IS NULL OR json_valid(variant_params));
+    ",
+    "
+    CREATE TABLE dialogueTlCandidates (
+        scriptid INTEGER,
+        address INTEGER,
+        idx INTEGER,
+        tl_body TEXT NOT NULL,
+        score REAL,
+        logprob REAL,
+        strategy TEXT NOT NULL CHECK (strategy IN ('direct', 'budget', 'split')),
+        n_predict INTEGER,
+        pieces INTEGER,
+        params TEXT CHECK (params IS NULL OR json_valid(params)),
+        PRIMARY KEY (scriptid, address, idx),
+        FOREIGN KEY (scriptid, address) REFERENCES dialogue)
+    WITHOUT ROWID, STRICT;
+    ",
+    "
+    ALTER TABLE dialogueTlMeta ADD COLUMN logprob REAL;
+    CREATE TABLE dialogueTlMemory (
+        scriptid INTEGER,
+        address INTEGER,
+        source_scriptid INTEGER NOT NULL,
+        source_address INTEGER NOT NULL,
+        reused INTEGER NOT NULL CHECK (reused IN (0, 1)),
+        PRIMARY KEY (scriptid, address),
+        FOREIGN KEY (scriptid, address) REFERENCES dialogueTl ON DELETE CASCADE)
+    WITHOUT ROWID, STRICT;
+    ",
+    // imports from before this can't be told apart from the model's own output, so only the
+    // manual backend's lines are taken as reviewed
+    "
+    ALTER TABLE dialogueTl ADD COLUMN reviewed INTEGER NOT NULL DEFAULT 0 CHECK (reviewed IN (0, 1));
+    UPDATE dialogueTl SET reviewed = 1
+    WHERE (scriptid, address) IN (SELECT scriptid, address FROM dialogueTlMeta WHERE params ->> '$.backend' = 'manual');
+    "
+];
+
+pub fn open(path: &Path) -> anyhow::Result<Connection> {
+    let mut db = Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_NO_MUTEX
+    )?;
+    db.pragma_update(None, "foreign_keys", true)?;
+
+    migrate(&mut db)?;
+
+    Ok(db)
+}
+
+fn migrate(db: &mut Connection) -> anyhow::Result<()> {
+    let tx = db.transaction()?;
+    let version: usize = tx.pragma_query_value(None, "user_version", |row| row.get(0))?;
+    anyhow::ensure!(version <= MIGRATIONS.len(), "database schema version {version} is newer than this build");
+
+    for sql in &MIGRATIONS[version..] {
+        tx.execute_batch(sql)?;
+    }
+    tx.pragma_update(None, "user_version", MIGRATIONS.len())?;
+    tx.commit()?;
+
+    Ok(())
+}
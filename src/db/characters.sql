@@ -0,0 +1,57 @@
+-- The roster as it stood when it was moved out of the source.
+INSERT INTO characters(jpspeaker, jpshort, enspeaker, gender, aliases) VALUES
+    ('少女', '', 'Girl', 'Female', '[]'),
+    ('魔法使い', '', 'Wizard', 'Male', '[]'),
+    ('玻ヰ璃[ハイリ]＝ラリック', '玻ヰ璃', 'Hairi Lalique', 'Female', '[]'),
+    ('カンパネラ', '', 'Campanella', 'Unknown', '[["カンちゃん","Kan-chan"]]'),
+    ('歌紫歌[カシカ]＝ガレ', '歌紫歌', 'Kashika Galle', 'Male', '[]'),
+    ('糸遠[シオン]＝ラリック', '糸遠', 'Shion Lalique', 'Male', '[]'),
+    ('衿栖[エリス]＝シュナイダー', '衿栖', 'Eris Schneider', 'Female', '[]'),
+    ('綸燈[リンドウ]＝ウェステリア', '綸燈', 'Rindo Westeria', 'Male', '[]'),
+    ('泣虎[ナトラ]＝ピオニー', '泣虎', 'Natra Peony', 'Male', '[]'),
+    ('廻螺[エラ]＝アマルリック', '廻螺', 'Ela Amalric', 'Male', '[]'),
+    ('憂漣[ユーレン]＝ミュラー', '憂漣', 'Ulen Muller', 'Male', '[]'),
+    ('紫鳶[シエン]＝クリノクロア', '紫鳶', 'Shien Clinochlore', 'Male', '[]'),
+    ('黒禰[クロネ]＝スピネル', '黒禰', 'Klone Spinel', 'Male', '[]'),
+    ('番人１', '', 'Guard 1', 'Unknown', '[]'),
+    ('男１', '', 'Man 1', 'Male', '[]'),
+    ('二人', '', 'Two People', 'Unknown', '[]'),
+    ('Ｍ', '', 'M', 'Unknown', '[]'),
+    ('番人２', '', 'Guard 2', 'Unknown', '[]'),
+    ('女性Ａ', '', 'Woman A', 'Female', '[]'),
+    ('男性Ａ', '', 'Man A', 'Male', '[]'),
+    ('子供Ａ', '', 'Child A', 'Unknown', '[]'),
+    ('紫鳶＆黒禰', '', 'Shien & Klone', 'Male', '[]'),
+    ('猿', '', 'Monkey', 'Unknown', '[]'),
+    ('ハルモニア', '', 'Harmonia', 'Unknown', '[]'),
+    ('瑪衣[メイ]', '瑪衣', 'Mei', 'Female', '[]'),
+    ('司書', '', 'Librarian', 'Unknown', '[]'),
+    ('研究者Ａ', '', 'Researcher A', 'Unknown', '[]'),
+    ('助手', '', 'Assistant', 'Unknown', '[]'),
+    ('研究者Ｂ', '', 'Researcher B', 'Unknown', '[]'),
+    ('アロマ店店主', '', 'Aroma Shop Owner', 'Unknown', '[]'),
+    ('番人Ａ', '', 'Guard A', 'Unknown', '[]'),
+    ('番人Ｂ', '', 'Guard B', 'Unknown', '[]'),
+    ('住民Ａ', '', 'Resident A', 'Unknown', '[]'),
+    ('住民Ｂ', '', 'Resident B', 'Unknown', '[]'),
+    ('刈鐘[カリガネ]', '刈鐘', 'Karigane', 'Unknown', '[]'),
+    ('三人', '', 'Three People', 'Unknown', '[]'),
+    ('女性記者Ａ', '', 'Female Reporter A', 'Female', '[]'),
+    ('晩歌[バンカ]', '晩歌', 'Banka', 'Unknown', '[]'),
+    ('少女Ａ', '', 'Girl A', 'Female', '[]'),
+    ('男性', '', 'Man', 'Male', '[]'),
+    ('店員', '', 'Shop Clerk', 'Unknown', '[]'),
+    ('霞[カスミ]', '霞', 'Kasumi', 'Female', '[]'),
+    ('おばあさん', '', 'Grandmother', 'Female', '[]'),
+    ('子供', '', 'Child', 'Unknown', '[]'),
+    ('おばあちゃん', '', 'Grandma', 'Female', '[]'),
+    ('少年', '', 'Boy', 'Male', '[]'),
+    ('女性１', '', 'Woman 1', 'Female', '[]'),
+    ('女性２', '', 'Woman 2', 'Female', '[]'),
+    ('門番', '', 'Gatekeeper', 'Unknown', '[]'),
+    ('歌紫歌＆糸遠', '', 'Kashika & Shion', 'Male', '[]'),
+    ('歌紫歌', '', 'Kashika', 'Male', '[]'),
+    ('男性１', '', 'Man 1', 'Male', '[]'),
+    ('初代Ｍ', '', 'First M', 'Unknown', '[]'),
+    ('王', '', 'King', 'Male', '[]'),
+    ('瑠璃[ルリ]', '瑠璃', 'Ruri', 'Female', '[]');
@@ -0,0 +1,20 @@
+-- The glossary as it stood when it was moved out of the source.
+-- I *think* this is all of the 透 terms. Localizing them is another story...
+INSERT INTO glossary(source, target, type, notes) VALUES
+    ('透京', 'Tokyo', 'Place', NULL),
+    ('透境門', 'Tokyomon', 'Place', NULL),
+    ('透迷ノ園', 'Tomei-no-sono', 'Place', NULL),
+    ('透淵ノ森', 'Toen-no-mori', 'Place', NULL),
+    ('透外ノ都', 'Togai-no-miyako', 'Place', NULL),
+    ('透彩ノ洞', 'Tosai-no-hora', 'Place', NULL),
+    ('透花ノ野', 'Toka-no-no', 'Place', NULL),
+    ('透木ノ集', 'Toboku-no-tsudoi', 'Place', NULL),
+    ('透澄ノ泉', 'Tocho-no-izumi', 'Place', NULL),
+    ('透花', 'Toka', 'Plant', NULL),
+    ('透櫻', 'Sukizakura', 'Plant', NULL),
+    ('白鴉', 'white crow', 'Animal', NULL),
+    ('カワウソ', 'otter', 'Animal', NULL),
+    ('ガラスの靴', 'glass slippers', 'Accessory', NULL),
+    ('黒死紋事件', 'Black Death Mark Incident', 'Event', 'copilot suggestion'),
+    ('時輪のアストロラビ', 'Astronomical Clock', 'Equipment', 'pulled this one out of my ass'),
+    ('アストロラーベ', 'Astrolabe', 'Equipment', 'pulled this one out of my ass');
@@ -0,0 +1,118 @@
+use std::{fs::File, io::{self, BufRead, BufReader, BufWriter, Write}, path::Path};
+
+use anyhow::Context;
+use rusqlite::{Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Line {
+    scriptid: u16,
+    address: u32,
+    #[serde(default, skip_deserializing)]
+    thread: String,
+    #[serde(default, skip_deserializing)]
+    speaker: Option<String>,
+    #[serde(default, skip_deserializing)]
+    body: String,
+    #[serde(default, skip_deserializing)]
+    variant_body: Option<String>,
+    tl_body: Option<String>,
+    #[serde(default)]
+    tl_variant_body: Option<String>,
+    // checked by a person, so the translation memory may trust it
+    #[serde(default)]
+    reviewed: bool
+}
+
+// One JSON object per line, so that exports diff and merge reasonably.
+pub fn export(db: &Connection, output: Option<&Path>, all: bool) -> anyhow::Result<()> {
+    let mut out: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(io::stdout().lock())
+    };
+
+    let mut stmt = db.prepare("
+        SELECT scriptid, address, thread, speaker, body, variant_body, tl_body, tl_variant_body, coalesce(reviewed, 0)
+        FROM dialogue LEFT NATURAL JOIN dialogueTl
+        WHERE ?1 OR tl_body IS NOT NULL
+        ORDER BY scriptid, address")?;
+
+    let mut n = 0;
+    let mut rows = stmt.query((all,))?;
+    while let Some(row) = rows.next()? {
+        let line = Line {
+            scriptid: row.get(0)?,
+            address: row.get(1)?,
+            thread: row.get(2)?,
+            speaker: row.get(3)?,
+            body: row.get(4)?,
+            variant_body: row.get(5)?,
+            tl_body: row.get(6)?,
+            tl_variant_body: row.get(7)?,
+            reviewed: row.get(8)?
+        };
+        serde_json::to_writer(&mut out, &line)?;
+        out.write_all(b"\n")?;
+        n += 1;
+    }
+    out.flush()?;
+
+    eprintln!("exported {n} lines");
+
+    Ok(())
+}
+
+// `reviewed` marks every line imported as reviewed; otherwise each line says for itself.
+pub fn import(db: &mut Connection, input: Option<&Path>, overwrite: bool, reviewed: bool) -> anyhow::Result<()> {
+    let input: Box<dyn BufRead> = match input {
+        Some(path) => Box::new(BufReader::new(File::open(path)?)),
+        None => Box::new(io::stdin().lock())
+    };
+
+    let tx = db.transaction()?;
+    let (mut imported, mut skipped) = (0, 0);
+    {
+        // updated in place rather than replaced, so unchanged lines keep what's recorded about them
+        let mut stmt = tx.prepare(if overwrite {
+            "INSERT INTO dialogueTl(scriptid, address, tl_body, tl_variant_body, reviewed) VALUES (?, ?, ?, ?, ?)
+            ON CONFLICT DO UPDATE SET tl_body = excluded.tl_body, tl_variant_body = excluded.tl_variant_body, reviewed = excluded.reviewed
+            WHERE tl_body IS NOT excluded.tl_body OR tl_variant_body IS NOT excluded.tl_variant_body OR reviewed IS NOT excluded.reviewed"
+        } else {
+            "INSERT OR IGNORE INTO dialogueTl(scriptid, address, tl_body, tl_variant_body, reviewed) VALUES (?, ?, ?, ?, ?)"
+        })?;
+        let mut current = tx.prepare("SELECT tl_body, tl_variant_body FROM dialogueTl WHERE scriptid = ? AND address = ?")?;
+
+        for (i, text) in input.lines().enumerate() {
+            let text = text?;
+            if text.trim().is_empty() { continue }
+
+            let line = serde_json::from_str::<Line>(&text).with_context(|| format!("line {}", i + 1))?;
+            let Some(tl_body) = line.tl_body else {
+                skipped += 1;
+                continue
+            };
+
+            let key = (line.scriptid, line.address);
+            let old = current.query_row(key, |row| <(String, Option<String>)>::try_from(row)).optional()?;
+            if overwrite && old.is_some_and(|old| old != (tl_body.clone(), line.tl_variant_body.clone())) {
+                // how the old text came about says nothing of the new
+                for table in ["dialogueTlMeta", "dialogueTlMemory", "dialogueTlCandidates"] {
+                    tx.execute(&format!("DELETE FROM {table} WHERE scriptid = ? AND address = ?"), key)?;
+                }
+            }
+
+            let changed = stmt.execute((line.scriptid, line.address, tl_body, line.tl_variant_body, reviewed || line.reviewed))
+                .with_context(|| format!("line {}: {}:{:X}", i + 1, line.scriptid, line.address))?;
+            if changed > 0 {
+                imported += 1;
+            } else {
+                skipped += 1;
+            }
+        }
+    }
+    tx.commit()?;
+
+    eprintln!("imported {imported} lines, skipped {skipped}");
+
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use anyhow::Context;
+use clap::Subcommand;
+use rusqlite::Connection;
+
+#[derive(Subcommand)]
+pub enum Command {
+    #[command(about = "List glossary terms")]
+    List {
+        #[arg(long, help = "Include disabled terms")]
+        all: bool
+    },
+    #[command(about = "Add a term")]
+    Add {
+        #[arg(help = "Term as it appears in the script")]
+        source: String,
+        #[arg(long, help = "Rendering to use in the translation")]
+        target: String,
+        #[arg(long = "type", help = "Kind of thing, e.g. Place, Plant, Event")]
+        kind: String,
+        #[arg(long, help = "Notes for translators; not shown to the model")]
+        notes: Option<String>
+    },
+    #[command(about = "Change fields of an existing term")]
+    Edit {
+        source: String,
+        #[arg(long)]
+        target: Option<String>,
+        #[arg(long = "type")]
+        kind: Option<String>,
+        #[arg(long)]
+        notes: Option<String>
+    },
+    #[command(about = "Include a term in prompts again")]
+    Enable {
+        source: String
+    },
+    #[command(about = "Keep a term out of prompts without deleting it")]
+    Disable {
+        source: String
+    },
+    #[command(about = "Remove a term")]
+    Remove {
+        source: String
+    }
+}
+
+pub fn run(db: &Connection, command: Command) -> anyhow::Result<()> {
+    let changed = match command {
+        Command::List { all } => {
+            let mut stmt = db.prepare("
+                SELECT source, target, type, notes, enabled
+                FROM glossary
+                WHERE ? OR enabled
+                ORDER BY rowid")?;
+            let mut rows = stmt.query((all,))?;
+            while let Some(row) = rows.next()? {
+                let (source, target, kind, notes, enabled) = <(String, String, String, Option<String>, bool)>::try_from(row)?;
+                print!("{source}\t{target}\t{kind}");
+                if !enabled {
+                    print!("\t(disabled)");
+                }
+                if let Some(notes) = notes {
+                    print!("\t# {notes}");
+                }
+                println!();
+            }
+            return Ok(());
+        },
+        Command::Add { source, target, kind, notes } => {
+            db.execute("INSERT INTO glossary(source, target, type, notes) VALUES (?, ?, ?, ?)", (&source, target, kind, notes))
+                .with_context(|| format!("could not add {source}"))?
+        },
+        Command::Edit { source, target, kind, notes } => {
+            db.execute("
+                UPDATE glossary SET
+                    target = coalesce(?2, target),
+                    type = coalesce(?3, type),
+                    notes = coalesce(?4, notes)
+                WHERE source = ?1",
+                (&source, target, kind, notes))?
+        },
+        Command::Enable { ref source } | Command::Disable { ref source } => {
+            let enabled = matches!(command, Command::Enable { .. });
+            db.execute("UPDATE glossary SET enabled = ? WHERE source = ?", (enabled, source))?
+        },
+        Command::Remove { source } => {
+            db.execute("DELETE FROM glossary WHERE source = ?", (&source,))?
+        }
+    };
+
+    anyhow::ensure!(changed > 0, "no such term");
+
+    Ok(())
+}
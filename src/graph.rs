@@ -0,0 +1,200 @@
+use std::{cmp::Reverse, collections::{BinaryHeap, HashSet}};
+use indexmap::IndexMap;
+use petgraph::{visit::EdgeRef, Directed, Graph};
+use rusqlite::Connection;
+
+pub type Node = (u16, String);
+
+// The virtual root
+pub const ROOT: u32 = 0;
+
+// Shortest-path tree over the script graph. Node 0 is a virtual root joined to every real root;
+// real node `i` is `vertices[i - 1]`.
+pub struct Tree {
+    vertices: IndexMap<Node, u8>,
+    pred: Vec<u32>,
+    children: Vec<Vec<u32>>,
+    // untranslated lines in each node's subtree, itself included
+    below: Vec<u32>
+}
+
+fn dijkstra(graph: &Graph<(), u8, Directed, u32>) -> Vec<u32> {
+    let mut seen = HashSet::with_capacity(graph.node_count());
+    let mut dist = vec![None; graph.node_count()];
+    let mut pred = vec![None; graph.node_count()];
+    let mut q = BinaryHeap::new();
+    
+    dist[0] = Some(0u32);
+    q.push(Reverse((0, 0)));
+
+    while let Some(Reverse((_, u))) = q.pop() {
+        if !seen.insert(u) { continue; }
+
+        for e in graph.edges(u.into()) {
+            let v = e.target().index();
+            let alt = dist[u as usize].unwrap().checked_add((*e.weight()).into()).unwrap();
+            if dist[v].is_none_or(|d| alt < d) {
+                pred[v] = Some(u);
+                dist[v] = Some(alt);
+                q.push(Reverse((alt, v as u32)));
+            }
+        }
+    }
+
+    assert!(dist.into_iter().all(|d| d.is_some()));
+    assert!(pred[0].is_none());
+    assert!(pred[1..].iter().all(|p| p.is_some()));
+    pred[0] = Some(0);
+
+    pred.into_iter().collect::<Option<_>>().unwrap()
+}
+
+impl Tree {
+    pub fn load(db: &Connection) -> anyhow::Result<Self> {
+        let vertices = {
+            let mut stmt = db.prepare("
+                WITH vertices(scriptid, thread) AS (
+                    SELECT tScriptid, tThread FROM graph
+                    UNION SELECT hScriptid, hThread FROM graph
+                    UNION SELECT scriptid, thread FROM dialogue)
+                SELECT scriptid, thread, COUNT(body) - COUNT(tl_body)
+                FROM vertices LEFT NATURAL JOIN dialogue LEFT NATURAL JOIN dialogueTl
+                GROUP BY scriptid, thread")?;
+            stmt.query_map((), |row| {
+                let (scriptid, thread, rem) = row.try_into()?;
+                Ok(((scriptid, thread), rem))
+            })?.collect::<Result<IndexMap<Node, u8>, _>>()?
+        };
+
+        let mut graph = Graph::<(), u8, Directed, u32>::new();
+
+        graph.reserve_exact_nodes(vertices.len() + 1);
+
+        {
+            let mut stmt = db.prepare("
+                SELECT tScriptid, tThread, hScriptid, hThread, count(body)
+                FROM graph LEFT JOIN dialogue ON (hScriptid, hThread) = (scriptid, thread)
+                GROUP BY tScriptid, tThread, hScriptid, hThread")?;
+
+            graph.extend_with_edges(stmt.query_map((), |row| {
+                let (t_scriptid, t_thread, h_scriptid, h_thread, weight): (u16, String, u16, String, u8) = row.try_into()?;
+                let t_idx = vertices.get_index_of(&(t_scriptid, t_thread)).unwrap();
+                let h_idx = vertices.get_index_of(&(h_scriptid, h_thread)).unwrap();
+                Ok((t_idx as u32 + 1, h_idx as u32 + 1, weight))
+            })?.map(Result::unwrap));
+        }
+
+        {
+            let mut stmt = db.prepare("
+                WITH roots(scriptid, thread) AS (
+                    SELECT tScriptid, tThread FROM graph
+                    UNION SELECT scriptid, thread FROM dialogue
+                    EXCEPT SELECT hScriptid, hThread from graph)
+                SELECT scriptid, thread, count(body)
+                FROM roots LEFT NATURAL JOIN dialogue
+                GROUP BY scriptid, thread")?;
+
+            graph.extend_with_edges(stmt.query_map((), |row| {
+                let (h_scriptid, h_thread, weight): (u16, String, u8) = row.try_into()?;
+                let h_idx = vertices.get_index_of(&(h_scriptid, h_thread)).unwrap();
+                Ok((0, h_idx as u32 + 1, weight))
+            })?.map(Result::unwrap));
+        }
+
+        let pred = dijkstra(&graph);
+
+        let mut children = vec![Vec::new(); pred.len()];
+        for (v, &p) in pred.iter().enumerate().skip(1) {
+            children[p as usize].push(v as u32);
+        }
+
+        let mut tree = Self { vertices, pred, children, below: Vec::new() };
+        tree.below = vec![0; tree.pred.len()];
+        // children come after their parent in preorder, so they are summed up first in reverse
+        for v in tree.preorder().into_iter().rev().filter(|&v| v != ROOT) {
+            let own = u32::from(*tree.vertices.get_index(v as usize - 1).unwrap().1);
+            tree.below[v as usize] += own;
+            tree.below[tree.pred[v as usize] as usize] += tree.below[v as usize];
+        }
+
+        Ok(tree)
+    }
+
+    // Every node, parents before children and each subtree in one piece
+    fn preorder(&self) -> Vec<u32> {
+        let mut order = Vec::with_capacity(self.pred.len());
+        let mut stack = vec![ROOT];
+        while let Some(v) = stack.pop() {
+            order.push(v);
+            stack.extend(self.children[v as usize].iter().rev());
+        }
+        order
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaves().count()
+    }
+
+    fn leaves(&self) -> impl Iterator<Item = u32> {
+        self.preorder().into_iter().filter(|&v| v != ROOT && self.children[v as usize].is_empty())
+    }
+
+    pub fn node(&self, v: u32) -> &Node {
+        self.vertices.get_index(v as usize - 1).unwrap().0
+    }
+
+    // Children of `v` with lines left to translate somewhere below them
+    pub fn branches(&self, v: u32) -> Vec<u32> {
+        self.children[v as usize].iter().copied().filter(|&c| self.below[c as usize] > 0).collect()
+    }
+
+    pub fn children(&self, v: u32) -> &[u32] {
+        &self.children[v as usize]
+    }
+
+    // Whether nothing is left to translate in `v` or anywhere below it
+    pub fn finished(&self, v: u32) -> bool {
+        self.below[v as usize] == 0
+    }
+
+    // Lines left to translate in `v` and below it, at load time
+    pub fn below(&self, v: u32) -> u32 {
+        self.below[v as usize]
+    }
+
+    // Every path from `v` down to a leaf, in preorder
+    pub fn paths(&self, v: u32) -> Vec<Vec<u32>> {
+        let mut paths = Vec::new();
+        let mut stack = vec![vec![v]];
+        while let Some(path) = stack.pop() {
+            let children = &self.children[*path.last().unwrap() as usize];
+            stack.extend(children.iter().rev().map(|&c| [&path[..], &[c]].concat()));
+            if children.is_empty() {
+                paths.push(path);
+            }
+        }
+        paths
+    }
+
+    // `v` and the nodes that follow it up to where the work left branches (or ends), and the
+    // branches there
+    pub fn chain(&self, mut v: u32) -> (Vec<u32>, Vec<u32>) {
+        let mut chain = vec![v];
+        loop {
+            let branches = self.branches(v);
+            match branches[..] {
+                [next] => {
+                    chain.push(next);
+                    v = next;
+                },
+                _ => return (chain, branches)
+            }
+        }
+    }
+
+    // Lines in `node` without a translation at load time
+    pub fn remaining(&self, node: &Node) -> u8 {
+        *self.vertices.get(node).unwrap()
+    }
+
+}
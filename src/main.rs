@@ -1,154 +1,143 @@
 
+mod candidates;
+mod config;
+mod db;
+mod exchange;
+mod glossary;
+mod graph;
+mod plan;
+mod roster;
+mod status;
 mod translate;
 
-use std::{cmp::Reverse, collections::{BTreeSet, BinaryHeap, HashSet}, path::PathBuf};
-use indexmap::IndexMap;
-use petgraph::{visit::EdgeRef, Directed, Graph};
-use rusqlite::{Connection, OpenFlags};
-use clap::Parser;
+use std::path::PathBuf;
+use rusqlite::Connection;
+use clap::{Parser, Subcommand};
+use anyhow::Context;
+
+use config::Config;
 
 #[derive(Parser)]
 struct Args {
-    #[arg(short, help = "Path to the working database file")]
-    file: PathBuf
+    #[arg(short, global = true, help = "Path to the working database file (overrides `database` from the project file)")]
+    file: Option<PathBuf>,
+    #[arg(short, long, global = true, help = "Path to the project file")]
+    config: Option<PathBuf>,
+    #[arg(short = 's', long = "set", global = true, value_name = "KEY=VALUE", help = "Override a project file key, e.g. -s server.endpoint=http://host:8080")]
+    overrides: Vec<String>,
+    #[command(subcommand)]
+    command: Command
 }
 
-fn dijkstra(graph: &Graph<(), u8, Directed, u32>) -> Vec<u32> {
-    let mut seen = HashSet::with_capacity(graph.node_count());
-    let mut dist = vec![None; graph.node_count()];
-    let mut pred = vec![None; graph.node_count()];
-    let mut q = BinaryHeap::new();
-    
-    dist[0] = Some(0u32);
-    q.push(Reverse((0, 0)));
-
-    while let Some(Reverse((_, u))) = q.pop() {
-        if !seen.insert(u) { continue; }
-
-        for e in graph.edges(u.into()) {
-            let v = e.target().index();
-            let alt = dist[u as usize].unwrap().checked_add((*e.weight()).into()).unwrap();
-            if dist[v].is_none_or(|d| alt < d) {
-                pred[v] = Some(u);
-                dist[v] = Some(alt);
-                q.push(Reverse((alt, v as u32)));
-            }
-        }
+#[derive(Subcommand)]
+enum Command {
+    #[command(about = "Walk the graph and translate every series with untranslated lines")]
+    Translate,
+    #[command(about = "Print the runs translate would make, without contacting the server")]
+    Plan {
+        #[arg(long, value_enum, default_value = "text")]
+        format: plan::Format
+    },
+    #[command(about = "List speakers the character roster can't resolve")]
+    Preflight,
+    #[command(about = "Show translation progress")]
+    Status {
+        #[arg(long, help = "Break progress down by script")]
+        by_script: bool
+    },
+    #[command(about = "Manage the character roster", subcommand)]
+    Characters(roster::Command),
+    #[command(about = "Manage glossary terms", subcommand)]
+    Glossary(glossary::Command),
+    #[command(about = "Review alternative translations kept by candidates.n", subcommand)]
+    Candidates(candidates::Command),
+    #[command(about = "Count tokens in TEXT (or stdin) as prompts are counted, with context.tokenizer if set")]
+    Tokenize {
+        text: Option<String>,
+        #[arg(long, help = "Print the token ids too")]
+        ids: bool
+    },
+    #[command(about = "Export translations as JSON lines")]
+    Export {
+        #[arg(short, long, help = "Output file (default: stdout)")]
+        output: Option<PathBuf>,
+        #[arg(long, help = "Include untranslated lines")]
+        all: bool
+    },
+    #[command(about = "Import translations from JSON lines, as written by export")]
+    Import {
+        #[arg(help = "Input file (default: stdin)")]
+        input: Option<PathBuf>,
+        #[arg(long, help = "Keep existing translations instead of replacing them")]
+        keep_existing: bool,
+        #[arg(long, help = "Mark every imported line as reviewed, for the translation memory to trust")]
+        reviewed: bool
     }
-
-    assert!(dist.into_iter().all(|d| d.is_some()));
-    assert!(pred[0].is_none());
-    assert!(pred[1..].iter().all(|p| p.is_some()));
-    pred[0] = Some(0);
-
-    pred.into_iter().collect::<Option<_>>().unwrap()
 }
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
+    let config = Config::load(args.config.as_deref(), &args.overrides)?;
 
-    let mut db = Connection::open_with_flags(
-        args.file,
-        OpenFlags::SQLITE_OPEN_READ_WRITE | OpenFlags::SQLITE_OPEN_NO_MUTEX
-    )?;
-    db.pragma_update(None, "foreign_keys", true)?;
-
-    db.execute("
-        CREATE TABLE IF NOT EXISTS dialogueTl (
-            scriptid INTEGER,
-            address INTEGER,
-            tl_body TEXT NOT NULL,
-            tl_variant_body TEXT,
-            PRIMARY KEY (scriptid, address),
-            FOREIGN KEY (scriptid, address) REFERENCES dialogue)
-        WITHOUT ROWID, STRICT
-    ", ())?;
-
-    let vertices = {
-        let mut stmt = db.prepare("
-            WITH vertices(scriptid, thread) AS (
-                SELECT tScriptid, tThread FROM graph
-                UNION SELECT hScriptid, hThread FROM graph
-                UNION SELECT scriptid, thread FROM dialogue)
-            SELECT scriptid, thread, COUNT(body) - COUNT(tl_body)
-            FROM vertices LEFT NATURAL JOIN dialogue LEFT NATURAL JOIN dialogueTl
-            GROUP BY scriptid, thread")?;
-        stmt.query_map((), |row| {
-            let (scriptid, thread, rem) = row.try_into()?;
-            Ok(((scriptid, thread), rem))
-        })?.collect::<Result<IndexMap<(u16, String), u8>, _>>()?
-    };
-
-    let mut graph = Graph::<(), u8, Directed, u32>::new();
-
-    graph.reserve_exact_nodes(vertices.len() + 1);
-
-    {
-        let mut stmt = db.prepare("
-            SELECT tScriptid, tThread, hScriptid, hThread, count(body)
-            FROM graph LEFT JOIN dialogue ON (hScriptid, hThread) = (scriptid, thread)
-            GROUP BY tScriptid, tThread, hScriptid, hThread")?;
-
-        graph.extend_with_edges(stmt.query_map((), |row| {
-            let (t_scriptid, t_thread, h_scriptid, h_thread, weight): (u16, String, u16, String, u8) = row.try_into()?;
-            let t_idx = vertices.get_index_of(&(t_scriptid, t_thread)).unwrap();
-            let h_idx = vertices.get_index_of(&(h_scriptid, h_thread)).unwrap();
-            Ok((t_idx as u32 + 1, h_idx as u32 + 1, weight))
-        })?.map(Result::unwrap));
+    // the only command that doesn't need the database
+    if let Command::Tokenize { text, ids } = args.command {
+        return tokenize(&config, text, ids).await;
     }
 
-    {
-        let mut stmt = db.prepare("
-            WITH roots(scriptid, thread) AS (
-                SELECT tScriptid, tThread FROM graph
-                UNION SELECT scriptid, thread FROM dialogue
-                EXCEPT SELECT hScriptid, hThread from graph)
-            SELECT scriptid, thread, count(body)
-            FROM roots LEFT NATURAL JOIN dialogue
-            GROUP BY scriptid, thread")?;
-
-        graph.extend_with_edges(stmt.query_map((), |row| {
-            let (h_scriptid, h_thread, weight): (u16, String, u8) = row.try_into()?;
-            let h_idx = vertices.get_index_of(&(h_scriptid, h_thread)).unwrap();
-            Ok((0, h_idx as u32 + 1, weight))
-        })?.map(Result::unwrap));
+    let file = args.file.as_ref().or(config.database.as_ref())
+        .context("no database given; pass -f or set `database` in the project file")?;
+    let mut db = db::open(file)?;
+
+    match args.command {
+        Command::Translate => translate(&config, &db).await,
+        Command::Plan { format } => plan::run(&db, format),
+        Command::Preflight => {
+            let unresolved = translate::preflight::scan(&config, &db, &translate::Roster::load(&db)?)?;
+            translate::preflight::report(&unresolved);
+            Ok(())
+        },
+        Command::Status { by_script } => status::run(&db, by_script),
+        Command::Characters(command) => roster::run(&mut db, command),
+        Command::Glossary(command) => glossary::run(&db, command),
+        Command::Candidates(command) => candidates::run(&mut db, command),
+        Command::Tokenize { .. } => unreachable!(),
+        Command::Export { output, all } => exchange::export(&db, output.as_deref(), all),
+        Command::Import { input, keep_existing, reviewed } => exchange::import(&mut db, input.as_deref(), !keep_existing, reviewed)
     }
+}
 
-    let pred = dijkstra(&graph);
-    let nodes = (0..graph.node_count() as u32).collect::<BTreeSet<_>>();
-
-    let cli = reqwest::Client::new();
-    
-    let mut n = 0;
-    for &(mut leaf) in nodes.difference(&pred.iter().copied().collect()) {
-        n += 1;
+async fn translate(config: &Config, db: &Connection) -> anyhow::Result<()> {
+    let tree = graph::Tree::load(db)?;
+    let roster = translate::Roster::load(db)?;
+    let glossary = translate::Glossary::load(db)?;
 
-        let mut path = vec![leaf];
-        while pred[leaf as usize] != 0 {
-            path.push(pred[leaf as usize]);
-            leaf = pred[leaf as usize];
-        }
-        let series = path.into_iter().rev().map(|v| vertices.get_index(v as usize - 1).unwrap().0).collect::<Vec<_>>();
+    let unresolved = translate::preflight::scan(config, db, &roster)?;
+    translate::preflight::report(&unresolved);
+    anyhow::ensure!(unresolved.is_empty() || !config.preflight.strict,
+        "refusing to translate with unresolved speakers; add them with `characters add` or unset preflight.strict");
 
-        if series.iter().all(|&v| *vertices.get(v).unwrap() == 0) {
-            // we've done all of these already
-            continue;
-        }
+    let memory = translate::Memory::load(config, db)?;
+    let examples = translate::Examples::load(config, db, &roster)?;
+    let driver = translate::Driver::new(config, &roster, &glossary, &memory, &examples)?;
 
-        for &&(scriptid, ref thread) in &series {
-            eprint!("-> {scriptid}:{thread} ");
-        }
+    translate::schedule::run(&driver, db, &tree).await;
+    eprintln!("{}", tree.leaf_count());
 
-        eprintln!();
+    Ok(())
+}
 
-        if let Err(e) = translate::run(&cli, &mut db, series).await {
-            eprintln!("SERIES FAILED: {e:?}");
-        }
+async fn tokenize(config: &Config, text: Option<String>, ids: bool) -> anyhow::Result<()> {
+    let text = match text {
+        Some(text) => text,
+        None => std::io::read_to_string(std::io::stdin())?
+    };
+    let tokens = translate::Tokenizer::new(config)?.encode("tokenize", &[&text]).await?.remove(0);
 
-        eprintln!();
+    println!("{}", tokens.len());
+    if ids {
+        println!("{tokens:?}");
     }
-    eprintln!("{n}");
 
     Ok(())
 }
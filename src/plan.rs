@@ -0,0 +1,121 @@
+use clap::ValueEnum;
+use rusqlite::Connection;
+use serde::Serialize;
+
+use crate::graph::{Tree, ROOT};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Format {
+    Text,
+    Json
+}
+
+#[derive(Serialize)]
+struct Plan {
+    runs: Vec<Run>,
+    totals: Totals
+}
+
+// Nodes `translate` takes in one go, up to where the tree branches; or, when `done`, a path down
+// to a leaf with nothing left to translate, which it skips
+#[derive(Serialize)]
+struct Run {
+    nodes: Vec<RunNode>,
+    lines: u64,
+    // the run whose context this one starts from, counting from 1
+    after: Option<usize>,
+    done: bool
+}
+
+#[derive(Serialize)]
+struct RunNode {
+    scriptid: u16,
+    thread: String,
+    untranslated: u8
+}
+
+#[derive(Default, Serialize)]
+struct Totals {
+    runs: usize,
+    done: usize,
+    lines: u64
+}
+
+// Walks the tree the way `translate::schedule::run` does with one slot. With more, runs on
+// different branches overlap, but each still starts from the same context. Branches with nothing
+// left are listed as done, a path per leaf, where the walk passes them by.
+pub fn run(db: &Connection, format: Format) -> anyhow::Result<()> {
+    let tree = Tree::load(db)?;
+
+    let describe = |path: &[u32]| path.iter().map(|&v| {
+        let node = tree.node(v);
+        RunNode { scriptid: node.0, thread: node.1.clone(), untranslated: tree.remaining(node) }
+    }).collect::<Vec<_>>();
+
+    let mut plan = Plan { runs: Vec::new(), totals: Totals::default() };
+    let done = |plan: &mut Plan, v, after| {
+        for path in tree.paths(v) {
+            plan.totals.done += 1;
+            plan.runs.push(Run { nodes: describe(&path), lines: 0, after, done: true });
+        }
+    };
+
+    let mut stack = tree.children(ROOT).iter().rev().map(|&v| (v, None)).collect::<Vec<_>>();
+    while let Some((v, after)) = stack.pop() {
+        if tree.finished(v) {
+            done(&mut plan, v, after);
+            continue;
+        }
+
+        let (chain, _) = tree.chain(v);
+        let nodes = describe(&chain);
+        let lines = nodes.iter().map(|n| u64::from(n.untranslated)).sum();
+
+        plan.totals.runs += 1;
+        plan.totals.lines += lines;
+        plan.runs.push(Run { nodes, lines, after, done: false });
+
+        // what the run passed by on its way, then where it ends
+        let this = Some(plan.runs.len());
+        let (&last, passed) = chain.split_last().unwrap();
+        for &c in passed.iter().flat_map(|&u| tree.children(u)).filter(|&&c| tree.finished(c)) {
+            done(&mut plan, c, this);
+        }
+        stack.extend(tree.children(last).iter().rev().map(|&c| (c, this)));
+    }
+
+    match format {
+        Format::Text => print_text(&plan),
+        Format::Json => println!("{}", serde_json::to_string_pretty(&plan)?)
+    }
+
+    Ok(())
+}
+
+fn print_text(plan: &Plan) {
+    for (i, run) in plan.runs.iter().enumerate() {
+        if run.done {
+            print!("{:>5}  {:>6}  ", i + 1, "done");
+        } else {
+            print!("{:>5}  {:>6}  ", i + 1, run.lines);
+        }
+        if let Some(after) = run.after {
+            print!("(after {after}) ");
+        }
+        for (j, n) in run.nodes.iter().enumerate() {
+            if j > 0 {
+                print!(" -> ");
+            }
+            print!("{}:{}", n.scriptid, n.thread);
+            if n.untranslated > 0 {
+                print!(" ({})", n.untranslated);
+            }
+        }
+        println!();
+    }
+
+    let t = &plan.totals;
+    println!();
+    println!("runs:   {} to run, {} done", t.runs, t.done);
+    println!("lines:  {} untranslated", t.lines);
+}
@@ -0,0 +1,102 @@
+use anyhow::Context;
+use clap::Subcommand;
+use rusqlite::{Connection, OptionalExtension};
+
+#[derive(Subcommand)]
+pub enum Command {
+    #[command(about = "List every character")]
+    List,
+    #[command(about = "Add a character")]
+    Add {
+        #[arg(help = "Speaker name as it appears in the script")]
+        jpspeaker: String,
+        #[arg(long, help = "English name")]
+        en: String,
+        #[arg(long, default_value = "", help = "Short form used in dialogue, e.g. the given name without its reading")]
+        short: String,
+        #[arg(long, default_value = "Unknown")]
+        gender: String,
+        #[arg(long = "alias", value_name = "JP=EN", value_parser = parse_alias)]
+        aliases: Vec<(String, String)>
+    },
+    #[command(about = "Change fields of an existing character")]
+    Edit {
+        jpspeaker: String,
+        #[arg(long)]
+        en: Option<String>,
+        #[arg(long)]
+        short: Option<String>,
+        #[arg(long)]
+        gender: Option<String>,
+        #[arg(long = "alias", value_name = "JP=EN", value_parser = parse_alias, help = "Add an alias, replacing any with the same JP form")]
+        aliases: Vec<(String, String)>,
+        #[arg(long = "remove-alias", value_name = "JP")]
+        remove_aliases: Vec<String>
+    },
+    #[command(about = "Remove a character")]
+    Remove {
+        jpspeaker: String
+    }
+}
+
+fn parse_alias(s: &str) -> anyhow::Result<(String, String)> {
+    let (jp, en) = s.split_once('=').context("expected JP=EN")?;
+    Ok((jp.to_owned(), en.to_owned()))
+}
+
+pub fn run(db: &mut Connection, command: Command) -> anyhow::Result<()> {
+    match command {
+        Command::List => {
+            let mut stmt = db.prepare("
+                SELECT jpspeaker, jpshort, enspeaker, gender, aliases
+                FROM characters
+                ORDER BY rowid")?;
+            let mut rows = stmt.query(())?;
+            while let Some(row) = rows.next()? {
+                let (jpspeaker, jpshort, enspeaker, gender, aliases) = <(String, String, String, String, String)>::try_from(row)?;
+                print!("{jpspeaker}\t{enspeaker}\t{gender}");
+                if !jpshort.is_empty() {
+                    print!("\tshort: {jpshort}");
+                }
+                if aliases != "[]" {
+                    print!("\taliases: {aliases}");
+                }
+                println!();
+            }
+        },
+        Command::Add { jpspeaker, en, short, gender, aliases } => {
+            db.execute("
+                INSERT INTO characters(jpspeaker, jpshort, enspeaker, gender, aliases)
+                VALUES (?, ?, ?, ?, ?)",
+                (&jpspeaker, short, en, gender, serde_json::to_string(&aliases)?))
+                .with_context(|| format!("could not add {jpspeaker}"))?;
+        },
+        Command::Edit { jpspeaker, en, short, gender, aliases, remove_aliases } => {
+            let tx = db.transaction()?;
+            let current = tx.query_row("SELECT aliases FROM characters WHERE jpspeaker = ?", (&jpspeaker,), |row| row.get::<_, String>(0))
+                .optional()?
+                .with_context(|| format!("no character {jpspeaker}"))?;
+
+            let mut current = serde_json::from_str::<Vec<(String, String)>>(&current)?;
+            current.retain(|(jp, _)| !remove_aliases.contains(jp) && !aliases.iter().any(|(a, _)| a == jp));
+            current.extend(aliases);
+
+            tx.execute("
+                UPDATE characters SET
+                    enspeaker = coalesce(?2, enspeaker),
+                    jpshort = coalesce(?3, jpshort),
+                    gender = coalesce(?4, gender),
+                    aliases = ?5
+                WHERE jpspeaker = ?1",
+                (&jpspeaker, en, short, gender, serde_json::to_string(&current)?))?;
+            tx.commit()?;
+        },
+        Command::Remove { jpspeaker } => {
+            if db.execute("DELETE FROM characters WHERE jpspeaker = ?", (&jpspeaker,))? == 0 {
+                anyhow::bail!("no character {jpspeaker}");
+            }
+        }
+    }
+
+    Ok(())
+}
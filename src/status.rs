@@ -0,0 +1,39 @@
+use rusqlite::Connection;
+
+pub fn run(db: &Connection, by_script: bool) -> anyhow::Result<()> {
+    let mut stmt = db.prepare("
+        SELECT scriptid, COUNT(body), COUNT(tl_body), COUNT(variant_body), COUNT(tl_variant_body)
+        FROM dialogue LEFT NATURAL JOIN dialogueTl
+        GROUP BY scriptid
+        ORDER BY scriptid")?;
+
+    let rows = stmt.query_map((), |row| <(u16, u64, u64, u64, u64)>::try_from(row))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (mut lines, mut done, mut variants, mut variants_done, mut scripts_done) = (0, 0, 0, 0, 0);
+    for &(scriptid, l, d, v, vd) in &rows {
+        if by_script {
+            println!("{scriptid:>5}  {d:>6}/{l:<6} {:>5.1}%", percent(d, l));
+        }
+        lines += l;
+        done += d;
+        variants += v;
+        variants_done += vd;
+        if d == l {
+            scripts_done += 1;
+        }
+    }
+
+    if by_script && !rows.is_empty() {
+        println!();
+    }
+    println!("lines:    {done}/{lines} ({:.1}%)", percent(done, lines));
+    println!("variants: {variants_done}/{variants} ({:.1}%)", percent(variants_done, variants));
+    println!("scripts:  {scripts_done}/{} complete", rows.len());
+
+    Ok(())
+}
+
+fn percent(n: u64, d: u64) -> f64 {
+    if d == 0 { 100.0 } else { n as f64 * 100.0 / d as f64 }
+}
@@ -0,0 +1,106 @@
+use std::{borrow::Cow, fmt::Display, hash::{Hash, Hasher}};
+
+use anyhow::Context;
+use rusqlite::Connection;
+
+#[derive(Debug, Default)]
+pub struct Character {
+    pub jpspeaker: String,
+    pub jpshort: String,
+    pub enspeaker: String,
+    pub gender: String,
+    pub aliases: Box<[(String, String)]>
+}
+
+impl Display for Character {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Name: {} ({}) | Gender: {}",
+            self.enspeaker,
+            self.jpspeaker,
+            self.gender
+        )?;
+
+        if !self.aliases.is_empty() {
+            f.write_str(" | Aliases: ")?;
+            let mut aliases = self.aliases.iter().peekable();
+            while let Some((jp, en)) = aliases.next() {
+                write!(f, "{en} ({jp})")?;
+                if aliases.peek().is_some() {
+                    f.write_str(", ")?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl PartialEq for Character {
+    fn eq(&self, other: &Self) -> bool {
+        self.jpspeaker.eq(&other.jpspeaker)
+    }
+}
+
+impl Eq for Character {}
+
+impl Hash for Character {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.jpspeaker.hash(state)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Roster(Box<[Character]>);
+
+impl Roster {
+    pub fn load(db: &Connection) -> anyhow::Result<Self> {
+        let mut stmt = db.prepare("
+            SELECT jpspeaker, jpshort, enspeaker, gender, aliases
+            FROM characters
+            ORDER BY rowid")?;
+
+        let characters = stmt.query_map((), |row| <(String, String, String, String, String)>::try_from(row))?.map(|row| {
+            let (jpspeaker, jpshort, enspeaker, gender, aliases) = row?;
+            let aliases = serde_json::from_str::<Vec<(String, String)>>(&aliases)
+                .with_context(|| format!("bad aliases for {jpspeaker}"))?;
+            Ok(Character { jpspeaker, jpshort, enspeaker, gender, aliases: aliases.into() })
+        }).collect::<anyhow::Result<_>>()?;
+
+        Ok(Self(characters))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Character> {
+        self.0.iter()
+    }
+
+    pub fn decode(&self, jpspeaker: &str) -> anyhow::Result<EnSpeaker<'_>> {
+        if jpspeaker == "？？？" {
+            return Ok(EnSpeaker::Str("???".into()));
+        }
+        for char in self.iter() {
+            if char.jpspeaker == jpspeaker {
+                return Ok(EnSpeaker::Character(char));
+            }
+
+            if jpspeaker.strip_prefix(char.jpspeaker.as_str()).is_some_and(|s| s == "の声") {
+                return Ok(EnSpeaker::Str((char.enspeaker.clone() + "'s voice").into()));
+            }
+        }
+        Err(anyhow::anyhow!("bro I don't know {jpspeaker}"))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum EnSpeaker<'a> {
+    Str(Cow<'static, str>),
+    Character(&'a Character)
+}
+
+impl Display for EnSpeaker<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EnSpeaker::Str(s) => Display::fmt(s, f),
+            EnSpeaker::Character(c) => Display::fmt(&c.enspeaker, f)
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::{cell::RefCell, collections::{HashMap, HashSet}};
+
+use rusqlite::Connection;
+
+use crate::config::Config;
+
+use super::{characters::Roster, normalize::Normalizer, Seen};
+
+fn bigrams(line: &str) -> HashSet<(char, char)> {
+    // padded, so one-character lines have some too
+    let chars = ['\0'].into_iter().chain(line.chars()).chain(['\0']).collect::<Vec<_>>();
+    chars.windows(2).map(|w| (w[0], w[1])).collect()
+}
+
+#[derive(Debug, Default)]
+struct Index {
+    // each with its number of bigrams
+    lines: Vec<(Seen, usize)>,
+    by_bigram: HashMap<(char, char), Vec<usize>>,
+    known: HashSet<String>
+}
+
+impl Index {
+    fn add(&mut self, seen: Seen) {
+        if !self.known.insert(seen.jpline.clone()) {
+            return;
+        }
+        let grams = bigrams(&seen.jpline);
+        for &g in &grams {
+            self.by_bigram.entry(g).or_default().push(self.lines.len());
+        }
+        self.lines.push((seen, grams.len()));
+    }
+}
+
+// Translated lines to show the model as examples, picked by how many character bigrams their
+// Japanese shares with the line being translated (Dice similarity)
+#[derive(Debug)]
+pub struct Examples {
+    n: usize,
+    min_similarity: f64,
+    index: RefCell<Index>
+}
+
+impl Examples {
+    pub fn load(config: &Config, db: &Connection, roster: &Roster) -> anyhow::Result<Self> {
+        let mut index = Index::default();
+
+        if config.examples.n > 0 {
+            let normalizer = Normalizer::new(config, false)?;
+            let mut stmt = db.prepare("
+                SELECT speaker, body, tl_body
+                FROM dialogue JOIN dialogueTl USING (scriptid, address)
+                ORDER BY scriptid, address")?;
+            let mut rows = stmt.query(())?;
+            while let Some(row) = rows.next()? {
+                let (speaker, body, tl_body) = <(Option<String>, String, String)>::try_from(row)?;
+                // speakers the roster doesn't know are left out rather than guessed
+                let speaker = speaker.map(|s| normalizer.speaker(&s)).and_then(|jp| {
+                    let en = roster.decode(&jp).ok()?.to_string();
+                    Some((jp, en))
+                });
+                index.add(Seen { speaker, jpline: normalizer.body(&body), enline: normalizer.translation(&tl_body) });
+            }
+        }
+
+        Ok(Self {
+            n: config.examples.n,
+            min_similarity: config.examples.min_similarity,
+            index: RefCell::new(index)
+        })
+    }
+
+    // The lines most like `line`, best first, leaving out what's already in `seen`
+    pub fn find(&self, line: &str, seen: &[Seen]) -> Vec<Seen> {
+        if self.n == 0 {
+            return Vec::new();
+        }
+
+        let index = self.index.borrow();
+        let grams = bigrams(line);
+        let mut shared = HashMap::<usize, usize>::new();
+        for g in &grams {
+            for &i in index.by_bigram.get(g).into_iter().flatten() {
+                *shared.entry(i).or_default() += 1;
+            }
+        }
+
+        let mut scored = shared.into_iter()
+            .map(|(i, shared)| (i, 2.0 * shared as f64 / (grams.len() + index.lines[i].1) as f64))
+            .filter(|&(i, score)| score >= self.min_similarity && !seen.iter().any(|s| s.jpline == index.lines[i].0.jpline))
+            .collect::<Vec<_>>();
+        // earlier lines first on ties
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(&b.0)));
+        scored.into_iter().take(self.n).map(|(i, _)| index.lines[i].0.clone()).collect()
+    }
+
+    // Makes a line just translated available as an example
+    pub fn learn(&self, seen: &Seen) {
+        if self.n > 0 {
+            self.index.borrow_mut().add(seen.clone());
+        }
+    }
+}
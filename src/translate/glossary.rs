@@ -0,0 +1,41 @@
+use std::fmt::Display;
+
+use rusqlite::Connection;
+
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct Term {
+    pub source: String,
+    pub target: String,
+    pub kind: String
+}
+
+impl Display for Term {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Name: {} ({}) | Type: {}", self.target, self.source, self.kind)
+    }
+}
+
+// Enabled glossary terms only
+#[derive(Debug, Default)]
+pub struct Glossary(Box<[Term]>);
+
+impl Glossary {
+    pub fn load(db: &Connection) -> anyhow::Result<Self> {
+        let mut stmt = db.prepare("
+            SELECT source, target, type
+            FROM glossary
+            WHERE enabled
+            ORDER BY rowid")?;
+
+        let terms = stmt.query_map((), |row| {
+            let (source, target, kind) = row.try_into()?;
+            Ok(Term { source, target, kind })
+        })?.collect::<Result<_, _>>()?;
+
+        Ok(Self(terms))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Term> {
+        self.0.iter()
+    }
+}
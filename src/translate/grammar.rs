@@ -0,0 +1,128 @@
+use std::fmt::Write as _;
+
+use crate::config::Config;
+
+use super::runaway::JAPANESE;
+
+// Past this many placeholders in a line, they have to come out in the line's own order rather than
+// in any order
+const MAX_REORDERED: usize = 4;
+
+// Builds the GBNF grammar a line's translation is sampled under
+#[derive(Clone, Debug)]
+pub struct Grammar {
+    brackets: bool,
+    japanese: bool,
+    // (source, target) of each placeholder to require
+    placeholders: Vec<(String, String)>
+}
+
+// A GBNF string literal
+fn literal(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => write!(out, "\\u{:04X}", c as u32).unwrap(),
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+    out
+}
+
+// What the English should open and close with, going by the Japanese
+fn brackets(line: &str) -> (Option<&'static str>, Option<&'static str>) {
+    let line = line.trim();
+    let open = match line.chars().next() {
+        Some('「' | '『') => Some("\""),
+        Some('（' | '(') => Some("("),
+        _ => None
+    };
+    let close = match line.chars().next_back() {
+        Some('」' | '』') => Some("\""),
+        Some('）' | ')') => Some(")"),
+        _ => None
+    };
+    (open, close)
+}
+
+// Every distinct ordering of `items`
+fn orders<'a>(items: &[&'a str]) -> Vec<Vec<&'a str>> {
+    if items.is_empty() {
+        return vec![Vec::new()];
+    }
+    let mut out = Vec::new();
+    for (i, &first) in items.iter().enumerate() {
+        if items[..i].contains(&first) {
+            continue;
+        }
+        let mut rest = items.to_vec();
+        rest.remove(i);
+        for mut order in orders(&rest) {
+            order.insert(0, first);
+            out.push(order);
+        }
+    }
+    out
+}
+
+impl Grammar {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            brackets: config.grammar.brackets,
+            japanese: config.grammar.japanese,
+            placeholders: if config.grammar.placeholders {
+                config.placeholders.values().map(|p| (p.source.clone(), p.target.clone())).collect()
+            } else {
+                Vec::new()
+            }
+        }
+    }
+
+    // Targets the translation of `line` must contain, in the order their sources appear in it
+    fn required(&self, line: &str) -> Vec<&str> {
+        let mut found = self.placeholders.iter()
+            .flat_map(|(source, target)| line.match_indices(source.as_str()).map(move |(i, _)| (i, target.as_str())))
+            .collect::<Vec<_>>();
+        found.sort_by_key(|&(i, _)| i);
+        found.into_iter().map(|(_, target)| target).collect()
+    }
+
+    // The grammar for translating `line`, forcing `speaker_prefix` first
+    pub fn build(&self, speaker_prefix: &str, line: &str) -> String {
+        let mut root = Vec::new();
+        let mut rules = String::new();
+        if !speaker_prefix.is_empty() {
+            root.push("prefix".to_owned());
+            writeln!(rules, "prefix ::= {}", literal(speaker_prefix)).unwrap();
+        }
+
+        let (open, close) = if self.brackets { brackets(line) } else { (None, None) };
+        root.extend(open.map(literal));
+        root.push("body".to_owned());
+        root.extend(close.map(literal));
+
+        let required = self.required(line);
+        let orders = if required.len() <= MAX_REORDERED { orders(&required) } else { vec![required] };
+        let body = orders.iter()
+            .map(|order| order.iter().fold("text".to_owned(), |seq, target| format!("{seq} {} text", literal(target))))
+            .collect::<Vec<_>>();
+        writeln!(rules, "body ::= {}", body.join(" | ")).unwrap();
+
+        let mut text = "\\x00".to_owned();
+        if self.japanese {
+            for (lo, hi) in JAPANESE {
+                write!(text, "\\u{:04X}-\\u{:04X}", lo as u32, hi as u32).unwrap();
+            }
+        }
+        writeln!(rules, "text ::= [^{text}]*").unwrap();
+
+        format!("root ::= {}\n{rules}", root.join(" "))
+    }
+}
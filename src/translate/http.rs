@@ -0,0 +1,102 @@
+use std::{fmt::Display, time::Duration};
+
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+
+use crate::config::{self, Config};
+
+// A non-success response, with whatever the server said about it
+#[derive(Clone, Debug)]
+pub struct HttpError {
+    pub status: StatusCode,
+    pub body: String
+}
+
+impl Display for HttpError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "server returned {}: {}", self.status, self.body)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+pub fn client(config: &Config) -> anyhow::Result<Client> {
+    let mut builder = Client::builder();
+    if let Some(secs) = config.retry.timeout_secs {
+        builder = builder.timeout(Duration::from_secs(secs));
+    }
+    Ok(builder.build()?)
+}
+
+pub async fn send(req: RequestBuilder) -> anyhow::Result<Response> {
+    let resp = req.send().await?;
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        return Err(HttpError { status, body }.into());
+    }
+    Ok(resp)
+}
+
+pub async fn send_json(req: RequestBuilder) -> anyhow::Result<serde_json::Value> {
+    // parsed here rather than with `json`, which reports a malformed body the same way as one cut
+    // short
+    let body = send(req).await?.bytes().await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+// Worth trying again: the server was unreachable, slow, busy, briefly broken or dropped the
+// connection, before responding or partway through the body. Anything else
+// (bad requests, malformed responses, running out of tokens) will fail the same way next time.
+pub fn is_transient(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            // reqwest reports a body cut short as one it couldn't decode; nothing here has it
+            // decode anything else
+            e.is_connect() || e.is_timeout() || e.is_request() || e.is_body() || e.is_decode()
+        } else if let Some(e) = cause.downcast_ref::<HttpError>() {
+            e.status == StatusCode::TOO_MANY_REQUESTS
+                || e.status.is_server_error()
+                || e.body.contains("slot unavailable")
+        } else {
+            false
+        }
+    })
+}
+
+#[derive(Clone, Debug)]
+pub struct Retry {
+    max_retries: u32,
+    initial_delay: Duration,
+    max_delay: Duration,
+    multiplier: f64
+}
+
+impl Retry {
+    pub fn new(config: &config::Retry) -> Self {
+        Self {
+            max_retries: config.max_retries,
+            initial_delay: Duration::from_millis(config.initial_delay_ms),
+            max_delay: Duration::from_millis(config.max_delay_ms),
+            multiplier: config.multiplier
+        }
+    }
+
+    // Runs `f` until it succeeds, fails permanently or runs out of retries. `what` names the
+    // request in the log.
+    pub async fn run<T, F>(&self, what: impl Display, mut f: impl FnMut() -> F) -> anyhow::Result<T>
+    where F: Future<Output = anyhow::Result<T>> {
+        let mut delay = self.initial_delay;
+        let mut attempt = 0;
+        loop {
+            match f().await {
+                Err(e) if attempt < self.max_retries && is_transient(&e) => {
+                    attempt += 1;
+                    eprintln!("{what}: {e:#}; retry {attempt}/{} in {delay:?}", self.max_retries);
+                    tokio::time::sleep(delay).await;
+                    delay = delay.mul_f64(self.multiplier).min(self.max_delay);
+                },
+                r => return r
+            }
+        }
+    }
+}
@@ -1,287 +1,334 @@
-#![allow(clippy::write_with_newline)]
-
-mod characters;
-
-use std::{collections::HashSet, fmt::{Display, Write as _}};
+use std::{cell::{Cell, RefCell}, collections::HashMap, rc::Rc};
 
 use anyhow::Context;
 use reqwest::Client;
 use serde_json::json;
-use rusqlite::{Connection, DropBehavior};
 
-use characters::{decode_jp_speaker, Character, EnSpeaker};
+use crate::config::{Config, Sampling};
 
-use crate::translate::llm::characters::ELEMENTS;
+use super::{characters::Roster, glossary::Glossary, grammar::Grammar, http::{self, Retry}, metadata::{example, metadata}, MaxTokensReachedError, PromptTooLongError, Request, RunawayError, Seen, Tokenizer, Translation, Translator, runaway::Runaway, template::Template};
 
-const N_CTX: usize = 1024;
-const N_PREDICT: usize = 64;
+// Rendered pieces to keep tokenized. The history of every series in flight is used on each of its
+// lines and stays; headers go stale as the metadata changes, and are dropped once there are more.
+const CACHED_PIECES: usize = 4096;
 
-#[derive(Debug)]
-pub struct Translator {}
+// A piece's tokens, and the call that last used them
+type Cached = (Rc<[u32]>, u64);
 
-#[derive(Clone, Debug)]
-struct Seen {
-    speaker: Option<(String, String)>,
-    jpline: String,
-    enline: String
+// llama.cpp's native /completion API
+#[derive(Debug)]
+pub struct LlamaCpp<'a> {
+    client: Client,
+    retry: Retry,
+    roster: &'a Roster,
+    glossary: &'a Glossary,
+    endpoint: String,
+    n_ctx: usize,
+    n_predict: usize,
+    keep: f64,
+    // tokens the few-shot examples may take up in the header
+    examples_budget: usize,
+    sampling: Sampling,
+    stream: bool,
+    cache_prompt: bool,
+    runaway: Runaway,
+    grammar: Grammar,
+    tokenizer: Tokenizer,
+    template: Box<Template>,
+    // Tokens of recently rendered headers and history entries. Each piece starts and ends on a
+    // special token, so a prompt's tokens are just its pieces' tokens laid end to end.
+    tokens: RefCell<HashMap<String, Cached>>,
+    calls: Cell<u64>
 }
 
-impl Display for Seen {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("<|start_header_id|>Japanese<|end_header_id|>]\n\n")?;
-        if let Some((ref jpspeaker, _)) = self.speaker {
-            write!(f, "[{jpspeaker}]: ")?;
-        }
-        write!(f, "{}<|eot_id|><|start_header_id|>English<|end_header_id|>\n\n", &self.jpline)?;
-        if let Some((_, ref enspeaker)) = self.speaker {
-            write!(f, "[{enspeaker}]: ")?;
-        }
-        write!(f, "{}<|eot_id|>", &self.enline)?;
-
-        Ok(())
+impl<'a> LlamaCpp<'a> {
+    pub fn new(config: &Config, roster: &'a Roster, glossary: &'a Glossary) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: http::client(config)?,
+            retry: Retry::new(&config.retry),
+            roster,
+            glossary,
+            endpoint: config.server.endpoint.trim_end_matches('/').to_owned(),
+            n_ctx: config.context.n_ctx,
+            n_predict: config.context.n_predict,
+            keep: config.context.keep.clamp(0.0, 1.0),
+            examples_budget: config.examples.max_tokens,
+            sampling: config.sampling.clone(),
+            stream: config.server.stream,
+            cache_prompt: config.server.cache_prompt,
+            runaway: Runaway::new(config),
+            grammar: Grammar::new(config),
+            tokenizer: Tokenizer::new(config)?,
+            template: Template::load(config)?.into(),
+            tokens: RefCell::default(),
+            calls: Cell::new(0)
+        })
     }
-}
 
-fn build_header(seen: &[Seen], next_speaker: Option<&str>, next_line: &str) -> anyhow::Result<String> {
-    let mut cs = seen.iter()
-        .filter_map(|s| s.speaker.as_ref())
-        .map(|(j, _)| j.as_str())
-        .chain(next_speaker)
-        .filter_map(|j| match decode_jp_speaker(j) {
-            Ok(EnSpeaker::Str(_)) => None,
-            Ok(EnSpeaker::Character(c)) => Some(Ok(c)),
-            Err(e) => Some(Err(e))
-        })
-        .collect::<anyhow::Result<HashSet<&Character>>>()?;
-
-    for c in characters::CHARACTERS.iter() {
-        if cs.contains(c) { continue }
-        let sp = if c.jpshort.is_empty() { c.jpspeaker } else { c.jpshort };
-        if next_line.contains(sp) {
-            cs.insert(c);
-            continue
-        }
-        for (a, _) in c.aliases.iter() {
-            if next_line.contains(a) {
-                cs.insert(c);
-                continue
+    // Tokens of each piece, tokenizing whichever aren't cached yet in a single request
+    async fn tokens(&self, what: &str, pieces: &[&str]) -> anyhow::Result<Vec<Rc<[u32]>>> {
+        let mut missing = Vec::new();
+        for &piece in pieces {
+            if !self.tokens.borrow().contains_key(piece) && !missing.contains(&piece) {
+                missing.push(piece);
             }
         }
-    }
 
-    let mut els = HashSet::<&'static str>::new();
-    for s in seen {
-        for &(el, elt) in ELEMENTS {
-            if s.jpline.contains(el) {
-                els.insert(elt);
-            }
+        let encoded = if missing.is_empty() {
+            Vec::new()
+        } else {
+            self.tokenizer.encode(what, &missing).await?
+        };
+
+        let now = self.calls.get() + 1;
+        self.calls.set(now);
+        let mut cache = self.tokens.borrow_mut();
+        for (piece, ids) in missing.into_iter().zip(encoded) {
+            cache.insert(piece.to_owned(), (ids.into(), now));
         }
-    }
-    for &(el, elt) in ELEMENTS {
-        if next_line.contains(el) {
-            els.insert(elt);
+        let tokens = pieces.iter().map(|p| {
+            let (ids, used) = cache.get_mut(*p).unwrap();
+            *used = now;
+            ids.clone()
+        }).collect();
+
+        // least recently used first, a quarter at a time so it isn't on every call
+        if cache.len() > CACHED_PIECES {
+            let mut used = cache.values().map(|&(_, used)| used).collect::<Vec<_>>();
+            used.sort_unstable();
+            let oldest_kept = used[used.len() - CACHED_PIECES * 3 / 4];
+            cache.retain(|_, &mut (_, used)| used >= oldest_kept);
         }
-    }
 
-    let mut header = "<|begin_of_text|><|start_header_id|>Metadata<|end_header_id|>\n".to_owned();
-    for c in cs {
-        write!(header, "\n[character] {c}")?;
-    }
-    for e in els {
-        write!(header, "\n{e}")?;
+        Ok(tokens)
     }
-    write!(header, "<|eot_id|>")?;
 
-    Ok(header)
-}
+    // The generated text, still with the speaker prefix, and the settings it was generated with
+    async fn get_completion(&self, prompt: &[u32], speaker: &str, n_predict: usize, req: &Request<'_>) -> anyhow::Result<Translation> {
+        let mut body = serde_json::to_value(&self.sampling)?;
+        body["prompt"] = json!(prompt);
+        body["n_predict"] = json!(n_predict);
+        body["grammar"] = json!(self.grammar.build(speaker, req.line));
+        body["stream"] = json!(self.stream);
+        body["cache_prompt"] = json!(self.cache_prompt);
+        if self.cache_prompt {
+            body["id_slot"] = json!(req.session.slot);
+        }
+        if let Some(seed) = req.seed {
+            body["seed"] = json!(seed);
+        }
+        if req.logprobs {
+            body["n_probs"] = json!(1);
+        }
 
-fn build_prompt(seen: &[Seen], next_speaker: Option<&str>, next_line: &str) -> anyhow::Result<String> {
-    let mut prompt = build_header(seen, next_speaker, next_line)?;
-    for s in seen {
-        write!(prompt, "{s}")?;
-    }
-    prompt.push_str("<|start_header_id|>Japanese<|end_header_id|>\n\n");
-    if let Some(next_speaker) = next_speaker {
-        write!(prompt, "[{next_speaker}]: ")?;
-    }
-    write!(prompt, "{next_line}<|eot_id|><|start_header_id|>English<|end_header_id|>\n\n")?;
-    //if let Some(enspeaker) = next_speaker.map(decode_jp_speaker).transpose()? {
-    //    write!(prompt, "[{enspeaker}]:")?;
-    //}
-
-    // force punctuation
-    //match next_line.chars().next() {
-    //    Some('（') => write!(prompt, "(")?,
-    //    Some('「') => write!(prompt, "\"")?,
-    //    _ => ()
-    //}
-
-    Ok(prompt)
-}
+        let post = self.client
+            .post(format!("{}/completion", self.endpoint))
+            .json(&body);
 
-#[derive(Clone, Debug)]
-struct MaxTokensReachedError(String);
+        let finished = if self.stream {
+            let finished = self.stream_completion(post, speaker.len()).await?;
+            if let Some(evaluated) = finished.evaluated {
+                req.session.record(prompt.len(), evaluated);
+            }
+            finished
+        } else {
+            let resp = http::send_json(post).await?;
+            if let Some(evaluated) = prompt_evaluated(&resp) {
+                req.session.record(prompt.len(), evaluated);
+            }
 
-impl Display for MaxTokensReachedError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("maximum number of tokens reached: ")?;
-        f.write_str(&self.0)
-    }
-}
+            let content = resp
+                .pointer("/content").context("no content")?
+                .as_str().context("content is not string")?.to_owned();
 
-impl std::error::Error for MaxTokensReachedError {}
-
-async fn tokenize(client: &Client, content: &str) -> anyhow::Result<Vec<u32>> {
-    client
-        .post("http://127.0.0.1:8080/tokenize")
-        .json(&json!({ "content": content }))
-        .send().await?.error_for_status()?
-        .json::<serde_json::Value>().await?
-        .pointer("/tokens").context("no tokens")?
-        .as_array().context("tokens is not array")?
-        .iter().map(|n| Ok(n.as_u64().context("not number")?.try_into()?)).collect()
-}
+            let stop_type = resp
+                .pointer("/stop_type").context("no stop type")?
+                .as_str().context("stop type is not str")?.to_owned();
 
-async fn get_completion(client: &Client, prompt: &[u32], speaker: &str) -> anyhow::Result<String> {
-    let resp = client
-        .post("http://127.0.0.1:8080/completion")
-        .json(&json!({
-             "prompt": prompt,
-             "n_predict": N_PREDICT,
-             "grammar": format!("root ::= \"{speaker}\" [^\\x00]*")
-        }))
-        .send().await?.error_for_status()?
-        .json::<serde_json::Value>().await?;
-    
-    let content = resp
-        .pointer("/content").context("no content")?
-        .as_str().context("content is not string")?.to_owned();
-
-    let stop_type = resp
-        .pointer("/stop_type").context("no stop type")?
-        .as_str().context("stop type is not str")?;
-
-    if stop_type != "eos" {
-        Err(MaxTokensReachedError(content).into())
-    } else {
-        Ok(content)
-    }
-}
+            if let Some(reason) = self.runaway.check(&content[speaker.len().min(content.len())..]) {
+                return Err(RunawayError { reason, partial: content }.into());
+            }
 
-impl Translator {
-    pub fn new() -> anyhow::Result<Self> {
-        Ok(Self {})
+            Finished {
+                content,
+                stop_type,
+                settings: resp.pointer("/generation_settings").cloned(),
+                logprobs: logprobs(&resp).collect(),
+                evaluated: None
+            }
+        };
+
+        if finished.stop_type != "eos" {
+            return Err(MaxTokensReachedError(finished.content).into());
+        }
+
+        body.as_object_mut().unwrap().remove("prompt");
+        let mut params = json!({ "backend": "llama.cpp", "request": body });
+        if let Some(settings) = finished.settings {
+            params["server"] = settings;
+        }
+        Ok(Translation {
+            text: finished.content,
+            params,
+            logprob: mean(&finished.logprobs)
+        })
     }
 
-    pub async fn translate(&self, cli: &Client, db: &mut Connection, series: impl IntoIterator<Item = &(u16, String)>) -> anyhow::Result<()> {
-        let mut seen = Vec::new();
-
-        let mut tx = db.transaction()?;
-        tx.set_drop_behavior(DropBehavior::Commit);
-        let mut stmt = tx.prepare_cached("
-            SELECT address, speaker, body, variant_body, tl_body
-            FROM dialogue LEFT NATURAL JOIN dialogueTl
-            WHERE scriptid = ? and thread = ?")?;
-
-        for &(scriptid, ref thread) in series {
-            eprintln!("\n--------- {scriptid}:{thread} ---------");
-            let mut rows = stmt.query((scriptid, thread))?;
-            while let Some(row) = rows.next()? {
-                let (address, mut speaker, mut line, mut line_variant, translation) = <(u32, Option<String>, String, Option<String>, Option<String>)>::try_from(row)?;
-
-                if let Some(ref mut speaker) = speaker {
-                    if speaker == "憂漣[ユーレン]ミュラー" || speaker == "憂漣[ユーレン]=ミュラー" {
-                        // fix a stupid artifact
-                        *speaker = "憂漣[ユーレン]＝ミュラー".into();
-                    } else {
-                        *speaker = speaker.trim().replace("#Name[1]", "玻ヰ璃").replace("#Name[2]", "ハイリ");
-                    }
-                }
-                line = line.replace("#Name[1]", "玻ヰ璃").replace("#Name[2]", "ハイリ");
-                if let Some(ref mut line_variant) = line_variant {
-                    *line_variant = line_variant.replace("#Name[1]", "玻ヰ璃").replace("#Name[2]", "ハイリ");
+    // Reads server-sent events as they come, echoing them to the console. Dropping the response
+    // partway closes the connection, which stops generation on the server.
+    async fn stream_completion(&self, post: reqwest::RequestBuilder, skip: usize) -> anyhow::Result<Finished> {
+        let mut resp = http::send(post).await?;
+        let mut buf = Vec::new();
+        let mut content = String::new();
+        let mut probs = Vec::new();
+
+        let result = 'read: loop {
+            let Some(chunk) = resp.chunk().await? else {
+                break Err(anyhow::anyhow!("stream ended without stopping"));
+            };
+            buf.extend_from_slice(&chunk);
+
+            while let Some(end) = buf.windows(2).position(|w| w == b"\n\n") {
+                let event = buf.drain(..end + 2).collect::<Vec<_>>();
+                let Some(data) = std::str::from_utf8(&event)?.trim_end().strip_prefix("data: ") else {
+                    continue;
+                };
+                let data = serde_json::from_str::<serde_json::Value>(data)?;
+
+                let piece = data.pointer("/content").and_then(|c| c.as_str()).unwrap_or_default();
+                content.push_str(piece);
+                probs.extend(logprobs(&data));
+                eprint!("{piece}");
+
+                if data.pointer("/stop").and_then(|s| s.as_bool()).unwrap_or(false) {
+                    let stop_type = data
+                        .pointer("/stop_type").context("no stop type")?
+                        .as_str().context("stop type is not str")?.to_owned();
+                    break 'read Ok(Finished {
+                        content,
+                        stop_type,
+                        settings: data.pointer("/generation_settings").cloned(),
+                        logprobs: probs,
+                        evaluated: prompt_evaluated(&data)
+                    });
                 }
 
-                if let Some(translation) = translation {
-                    seen.push(Seen {
-                        speaker: speaker.map(|speaker| {
-                            let decoded = decode_jp_speaker(&speaker)?.to_string();
-                            Ok::<_, anyhow::Error>((speaker, decoded))
-                        }).transpose()?,
-                        jpline: line,
-                        enline: translation
-                    });
-                    continue;
+                if let Some(reason) = self.runaway.check(&content[skip.min(content.len())..]) {
+                    break 'read Err(RunawayError { reason, partial: content }.into());
                 }
+            }
+        };
+        eprintln!();
 
-                eprintln!("address = {address:X}");
-                let speaker_prefix = speaker.as_ref().map_or(Ok::<_, anyhow::Error>(String::new()),
-                    |speaker| Ok(format!("[{}]: ", decode_jp_speaker(speaker)?)))?;
-                
-                let translation_variant = match line_variant {
-                    Some(line) => {
-                        // translate the variant in a vacuum
-                        let mut seen = seen.clone();
-                        let prompt = loop {
-                            let prompt = build_prompt(&seen, speaker.as_deref(), &line)?;
-                            let tokens = tokenize(cli, &prompt).await?;
-                            if tokens.len() > N_CTX-N_PREDICT {
-                                let md = (seen.len() / 16).max(1);
-                                seen.drain(0..md);
-                                continue;
-                            }
-                            break tokens
-                        };
-
-                        Some(get_completion(cli, &prompt, &speaker_prefix).await?
-                            .strip_prefix(&speaker_prefix).unwrap().trim().to_owned())
-                    },
-                    None => None
-                };
+        result
+    }
+}
 
-                let translation = {
-                    let prompt = loop {
-                        let prompt = build_prompt(&seen, speaker.as_deref(), &line)?;
-                        let tokens = tokenize(cli, &prompt).await?;
-                        if tokens.len() > N_CTX-N_PREDICT {
-                            // Fairly conservative exponential reduction
-                            let md = (seen.len() / 16).max(1);
-                            seen.drain(0..md);
-                            continue;
-                        }
-                        break tokens
-                    };
-
-                    get_completion(cli, &prompt, &speaker_prefix).await?
-                        .strip_prefix(&speaker_prefix).unwrap().trim().to_owned()
-                };
+// A completion the server is done with
+struct Finished {
+    content: String,
+    stop_type: String,
+    settings: Option<serde_json::Value>,
+    // of each sampled token, when asked for with n_probs
+    logprobs: Vec<f64>,
+    // prompt tokens the server evaluated rather than took from its cache, when it reports timings
+    evaluated: Option<usize>
+}
 
-                eprintln!("{speaker_prefix}{translation}\n");
-                
-                if let Some(ref variant) = translation_variant {
-                    eprintln!("[VARIANT] {speaker_prefix}{variant}\n");
-                }
+fn prompt_evaluated(data: &serde_json::Value) -> Option<usize> {
+    data.pointer("/timings/prompt_n")?.as_u64().map(|n| n as usize)
+}
+
+fn logprobs(data: &serde_json::Value) -> impl Iterator<Item = f64> + '_ {
+    data.pointer("/completion_probabilities").and_then(|p| p.as_array()).into_iter().flatten()
+        .filter_map(|p| p.pointer("/logprob")?.as_f64())
+}
+
+pub fn mean(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
 
-                tx.prepare_cached("
-                    INSERT OR REPLACE INTO dialogueTl(scriptid, address, tl_body, tl_variant_body)
-                    VALUES (?, ?, ?, ?)")?
-                    .execute((scriptid, address, &translation, translation_variant))?;
-
-                seen.push(Seen {
-                    speaker: speaker.map(|speaker| {
-                        let decoded = decode_jp_speaker(&speaker)?.to_string();
-                        Ok::<_, anyhow::Error>((speaker, decoded))
-                    }).transpose()?,
-                    jpline: line,
-                    enline: translation
-                });
+impl Translator for LlamaCpp<'_> {
+    async fn translate(&self, seen: &mut Vec<Seen>, req: &Request<'_>) -> anyhow::Result<Translation> {
+        let jpspeaker = req.speaker.map(|(jp, _)| jp.as_str());
+        let speaker_prefix = self.template.speaker(req.speaker.map(|(_, en)| en.as_str()));
+        let n_predict = req.n_predict.unwrap_or(self.n_predict);
+
+        let what = format!("{}:{:X}", req.scriptid, req.address);
+        let available = self.n_ctx.saturating_sub(n_predict);
+
+        // as many of the most similar examples as fit their budget. Each is counted on its own, as
+        // they don't end on special tokens and could merge with the next.
+        let mut examples = req.examples.iter().map(example).collect::<Vec<_>>();
+        let mut used = 0;
+        let mut fit = 0;
+        for e in &examples {
+            used += self.tokens(&what, &[e]).await?[0].len();
+            if used > self.examples_budget {
+                break;
             }
-            
-            drop(rows);
+            fit += 1;
+        }
+        examples.truncate(fit);
+        let header_for = |seen: &[Seen]| -> anyhow::Result<String> {
+            let mut lines = metadata(self.roster, self.glossary, seen, jpspeaker, req.line)?;
+            lines.extend(examples.iter().cloned());
+            Ok(self.template.header(&lines))
+        };
+
+        let tail = self.template.tail(jpspeaker, req.line, req.hint);
+        let header = header_for(seen)?;
+        let history = seen.iter().map(|s| self.template.exchange(s)).collect::<Vec<_>>();
+
+        let mut pieces = vec![header.as_str(), tail.as_str()];
+        pieces.extend(history.iter().map(String::as_str));
+        let tokens = self.tokens(&what, &pieces).await?;
+        let (fixed, history) = tokens.split_at(2);
+
+        // keep as much recent history as fits. The header only shrinks as history is dropped.
+        // Once some has to go, drop a block of it, so the next few lines still fit behind the
+        // same prefix and the server can reuse its cache.
+        let fixed_len = fixed[0].len() + fixed[1].len();
+        let total = fixed_len + history.iter().map(|e| e.len()).sum::<usize>();
+        let budget = if total <= available {
+            available
+        } else {
+            fixed_len + (available.saturating_sub(fixed_len) as f64 * self.keep) as usize
+        };
+        let mut used = fixed_len;
+        let mut keep = 0;
+        for entry in history.iter().rev() {
+            if used + entry.len() > budget {
+                break;
+            }
+            used += entry.len();
+            keep += 1;
+        }
+        if used > available {
+            return Err(PromptTooLongError { tokens: used, available }.into());
         }
-        drop(stmt);
-        tx.commit()?;
 
-        Ok(())
+        let dropped = seen.len() - keep;
+        let header = if dropped > 0 {
+            seen.drain(..dropped);
+            let header = header_for(seen)?;
+            self.tokens(&what, &[&header]).await?.remove(0)
+        } else {
+            fixed[0].clone()
+        };
+
+        let mut prompt = header.to_vec();
+        for entry in &history[dropped..] {
+            prompt.extend_from_slice(entry);
+        }
+        prompt.extend_from_slice(&fixed[1]);
+
+        let completion = self.retry.run(format_args!("{what} completion"),
+            || self.get_completion(&prompt, &speaker_prefix, n_predict, req)).await?;
+
+        Ok(Translation {
+            text: completion.text.strip_prefix(&speaker_prefix).unwrap().trim().to_owned(),
+            ..completion
+        })
     }
 }
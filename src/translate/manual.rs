@@ -0,0 +1,37 @@
+use std::io::{self, BufRead, Write};
+
+use serde_json::json;
+
+use super::{Request, Seen, Translation, Translator};
+
+// Asks whoever is at the terminal. An empty answer stops the series.
+#[derive(Debug)]
+pub struct Manual;
+
+impl Translator for Manual {
+    async fn translate(&self, seen: &mut Vec<Seen>, req: &Request<'_>) -> anyhow::Result<Translation> {
+        let mut stderr = io::stderr().lock();
+        if let Some(s) = seen.last() {
+            if let Some((_, ref en)) = s.speaker {
+                write!(stderr, "[{en}]: ")?;
+            }
+            writeln!(stderr, "{}", s.enline)?;
+        }
+        if let Some((jp, en)) = req.speaker {
+            write!(stderr, "[{jp}] [{en}]: ")?;
+        }
+        writeln!(stderr, "{}", req.line)?;
+        if let Some(hint) = req.hint {
+            writeln!(stderr, "(translated before as: {hint})")?;
+        }
+        write!(stderr, "> ")?;
+        stderr.flush()?;
+
+        let mut answer = String::new();
+        io::stdin().lock().read_line(&mut answer)?;
+        let answer = answer.trim();
+        anyhow::ensure!(!answer.is_empty(), "no translation given for {}:{:X}", req.scriptid, req.address);
+
+        Ok(Translation { text: answer.to_owned(), params: json!({ "backend": "manual" }), logprob: None })
+    }
+}
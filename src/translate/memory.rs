@@ -0,0 +1,80 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use rusqlite::Connection;
+
+use crate::config::{Config, MemoryMode};
+
+use super::{normalize::Normalizer, Translation};
+
+// An earlier translation of a line
+#[derive(Clone, Debug)]
+pub struct Entry {
+    // as the model would have written it, with placeholders
+    pub text: String,
+    pub scriptid: u16,
+    pub address: u32
+}
+
+// Trusted translations by normalized Japanese speaker and line: reviewed ones (imported as such,
+// or from the manual backend), and the model's own if it was sure enough of them
+#[derive(Debug)]
+pub struct Memory {
+    pub mode: MemoryMode,
+    min_logprob: Option<f64>,
+    entries: RefCell<HashMap<(Option<String>, String), Entry>>
+}
+
+impl Memory {
+    pub fn load(config: &Config, db: &Connection) -> anyhow::Result<Self> {
+        let mode = config.memory.mode;
+        let min_logprob = config.memory.min_logprob;
+        let mut entries = HashMap::new();
+
+        if mode != MemoryMode::Off {
+            // keyed the same way the driver sees lines
+            let normalizer = Normalizer::new(config, false)?;
+            let mut stmt = db.prepare("
+                SELECT scriptid, address, speaker, body, tl_body
+                FROM dialogue
+                    JOIN dialogueTl USING (scriptid, address)
+                    LEFT JOIN dialogueTlMeta m USING (scriptid, address)
+                    LEFT JOIN dialogueTlMemory r USING (scriptid, address)
+                WHERE r.reused IS NOT 1 AND (reviewed OR m.logprob >= ?)
+                -- reviewed translations first
+                ORDER BY NOT reviewed, scriptid, address")?;
+            let mut rows = stmt.query([min_logprob])?;
+            while let Some(row) = rows.next()? {
+                let (scriptid, address, speaker, body, tl_body) = <(u16, u32, Option<String>, String, String)>::try_from(row)?;
+                let key = (speaker.map(|s| normalizer.speaker(&s)), normalizer.body(&body));
+                entries.entry(key).or_insert_with(|| Entry { text: normalizer.translation(&tl_body), scriptid, address });
+            }
+        }
+
+        Ok(Self { mode, min_logprob, entries: RefCell::new(entries) })
+    }
+
+    // Whether new translations need their log probability to be judged
+    pub fn wants_logprobs(&self) -> bool {
+        self.mode != MemoryMode::Off && self.min_logprob.is_some()
+    }
+
+    // An earlier translation of `line` said by `speaker`, both normalized
+    pub fn find(&self, speaker: Option<&str>, line: &str) -> Option<Entry> {
+        if self.mode == MemoryMode::Off {
+            return None;
+        }
+        self.entries.borrow().get(&(speaker.map(str::to_owned), line.to_owned())).cloned()
+    }
+
+    // Remembers a translation just made, if it's one to trust
+    pub fn learn(&self, speaker: Option<&str>, line: &str, tl: &Translation, scriptid: u16, address: u32) {
+        let reviewed = tl.params["backend"] == "manual";
+        let sure = self.min_logprob.is_some_and(|min| tl.logprob.is_some_and(|l| l >= min));
+        if self.mode == MemoryMode::Off || !(reviewed || sure) {
+            return;
+        }
+        self.entries.borrow_mut()
+            .entry((speaker.map(str::to_owned), line.to_owned()))
+            .or_insert_with(|| Entry { text: tl.text.clone(), scriptid, address });
+    }
+}
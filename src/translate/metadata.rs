@@ -0,0 +1,40 @@
+use indexmap::IndexSet;
+
+use super::{characters::{Character, EnSpeaker, Roster}, glossary::{Glossary, Term}, Seen};
+
+// Background for the model: the characters and glossary terms that matter around `next_line`,
+// one `[kind] ...` entry each. Shared by every prompt format.
+// Entries are in the order the lines bring them up, so from one line to the next the metadata
+// stays the same unless something new comes up, and keeps the prompt's prefix cacheable.
+pub fn metadata(roster: &Roster, glossary: &Glossary, seen: &[Seen], next_speaker: Option<&str>, next_line: &str) -> anyhow::Result<Vec<String>> {
+    let lines = seen.iter()
+        .map(|s| (s.speaker.as_ref().map(|(j, _)| j.as_str()), s.jpline.as_str()))
+        .chain([(next_speaker, next_line)]);
+
+    let mut cs = IndexSet::<&Character>::new();
+    let mut els = IndexSet::<&Term>::new();
+    for (speaker, line) in lines {
+        if let Some(speaker) = speaker && let EnSpeaker::Character(c) = roster.decode(speaker)? {
+            cs.insert(c);
+        }
+
+        for c in roster.iter() {
+            let sp = if c.jpshort.is_empty() { &c.jpspeaker } else { &c.jpshort };
+            if line.contains(sp.as_str()) || c.aliases.iter().any(|(a, _)| line.contains(a.as_str())) {
+                cs.insert(c);
+            }
+        }
+
+        els.extend(glossary.iter().filter(|t| line.contains(&t.source)));
+    }
+
+    Ok(cs.into_iter().map(|c| format!("[character] {c}"))
+        .chain(els.into_iter().map(|e| format!("[element] {e}")))
+        .collect())
+}
+
+// A similar line translated before, as a metadata entry of its own
+pub fn example(s: &Seen) -> String {
+    let (jp, en) = s.speaker.as_ref().map_or((String::new(), String::new()), |(jp, en)| (format!("[{jp}]: "), format!("[{en}]: ")));
+    format!("[example] {jp}{} => {en}{}", s.jpline, s.enline)
+}
@@ -1,13 +1,425 @@
+mod characters;
+mod examples;
+mod glossary;
+mod grammar;
+mod http;
 mod llm;
+mod manual;
+mod memory;
+mod metadata;
+mod nbest;
+mod normalize;
+mod openai;
+pub mod preflight;
+mod recovery;
+mod runaway;
+pub mod schedule;
+mod template;
+mod tokenizer;
 
-use rusqlite::Connection;
-use reqwest::Client;
+use std::{cell::Cell, fmt::Display};
 
-use llm::Translator as LlmTramslator;
+use anyhow::Context;
+use rusqlite::{Connection, Transaction};
 
-pub async fn run(cli: &Client, db: &mut Connection, series: impl IntoIterator<Item = &(u16, String)>) -> anyhow::Result<()> {
-    let tl = LlmTramslator::new()?;
-    tl.translate(cli, db, series).await?;
+use crate::config::{self, Config, MemoryMode};
 
-    Ok(())
+use nbest::{Candidate, NBest};
+use normalize::Normalizer;
+use recovery::{Recovery, Strategy};
+
+pub use characters::Roster;
+pub use examples::Examples;
+pub use glossary::Glossary;
+pub use memory::Memory;
+pub use tokenizer::Tokenizer;
+
+// A line that has already been translated, as context for the next one
+#[derive(Clone, Debug)]
+pub struct Seen {
+    // (Japanese, English)
+    pub speaker: Option<(String, String)>,
+    pub jpline: String,
+    pub enline: String
+}
+
+// What every request of one series shares: the server slot it is pinned to, and how much of its
+// prompts the server could skip evaluating thanks to its cache
+#[derive(Debug, Default)]
+pub struct Session {
+    pub slot: usize,
+    tokens: Cell<usize>,
+    evaluated: Cell<usize>
+}
+
+impl Session {
+    pub fn new(slot: usize) -> Self {
+        Self { slot, ..Self::default() }
+    }
+
+    // A prompt of `tokens` tokens of which the server evaluated `evaluated`
+    pub fn record(&self, tokens: usize, evaluated: usize) {
+        self.tokens.set(self.tokens.get() + tokens);
+        self.evaluated.set(self.evaluated.get() + evaluated);
+    }
+}
+
+impl Display for Session {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (tokens, evaluated) = (self.tokens.get(), self.evaluated.get());
+        let saved = tokens.saturating_sub(evaluated);
+        write!(f, "{evaluated} of {tokens} prompt tokens evaluated, {saved} ({:.1}%) from cache",
+            100.0 * saved as f64 / tokens.max(1) as f64)
+    }
+}
+
+pub struct Request<'a> {
+    pub session: &'a Session,
+    pub scriptid: u16,
+    pub address: u32,
+    // (Japanese, English)
+    pub speaker: Option<&'a (String, String)>,
+    pub line: &'a str,
+    // output budget in place of the configured one
+    pub n_predict: Option<usize>,
+    // seed in place of the configured one
+    pub seed: Option<u32>,
+    // ask for token probabilities to fill in `Translation::logprob`
+    pub logprobs: bool,
+    // a trusted earlier translation of the same line, to show the model
+    pub hint: Option<&'a str>,
+    // similar lines translated before, best first
+    pub examples: &'a [Seen]
+}
+
+// A backend's answer to one request
+#[derive(Clone, Debug)]
+pub struct Translation {
+    // the bare English line, without any speaker prefix
+    pub text: String,
+    // the settings it was generated with, as sent and as reported back by the server
+    pub params: serde_json::Value,
+    // mean log probability of the generated tokens, if the backend was asked and could tell
+    pub logprob: Option<f64>
+}
+
+pub trait Translator {
+    // Translate one normalized line given what came before it. Backends with a limited context may
+    // drop entries from the front of `seen`; the driver keeps whatever is left for the next line.
+    async fn translate(&self, seen: &mut Vec<Seen>, req: &Request<'_>) -> anyhow::Result<Translation>;
+}
+
+// The model ran out of its output budget before finishing the line
+#[derive(Clone, Debug)]
+pub struct MaxTokensReachedError(pub String);
+
+impl Display for MaxTokensReachedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("maximum number of tokens reached: ")?;
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for MaxTokensReachedError {}
+
+// The output went wrong partway through (a repetition loop, Japanese, ...) and was cut short
+#[derive(Clone, Debug)]
+pub struct RunawayError {
+    pub reason: String,
+    pub partial: String
+}
+
+impl Display for RunawayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "generation stopped, {}: {}", self.reason, self.partial)
+    }
+}
+
+impl std::error::Error for RunawayError {}
+
+// The prompt leaves no room for the output budget even with all history dropped
+#[derive(Clone, Debug)]
+pub struct PromptTooLongError {
+    pub tokens: usize,
+    pub available: usize
+}
+
+impl Display for PromptTooLongError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "prompt is {} tokens without any history, but only {} fit", self.tokens, self.available)
+    }
+}
+
+impl std::error::Error for PromptTooLongError {}
+
+pub enum Backend<'a> {
+    LlamaCpp(llm::LlamaCpp<'a>),
+    OpenAi(openai::OpenAi<'a>),
+    Manual(manual::Manual)
+}
+
+impl<'a> Backend<'a> {
+    pub fn new(config: &Config, roster: &'a Roster, glossary: &'a Glossary) -> anyhow::Result<Self> {
+        Ok(match config.backend {
+            config::Backend::LlamaCpp => Self::LlamaCpp(llm::LlamaCpp::new(config, roster, glossary)?),
+            config::Backend::OpenAi => Self::OpenAi(openai::OpenAi::new(config, roster, glossary)?),
+            config::Backend::Manual => Self::Manual(manual::Manual)
+        })
+    }
+}
+
+impl Translator for Backend<'_> {
+    async fn translate(&self, seen: &mut Vec<Seen>, req: &Request<'_>) -> anyhow::Result<Translation> {
+        match self {
+            Self::LlamaCpp(tl) => tl.translate(seen, req).await,
+            Self::OpenAi(tl) => tl.translate(seen, req).await,
+            Self::Manual(tl) => tl.translate(seen, req).await
+        }
+    }
+}
+
+// The one way translations reach the database. Series run concurrently, but on one thread, and
+// each write finishes without yielding, so writes never interleave; every line is committed on its
+// own as soon as it is done.
+pub struct Writer<'c> {
+    db: &'c Connection
+}
+
+impl<'c> Writer<'c> {
+    pub fn new(db: &'c Connection) -> Self {
+        Self { db }
+    }
+
+    pub fn write<T>(&self, f: impl FnOnce(&Transaction) -> anyhow::Result<T>) -> anyhow::Result<T> {
+        let tx = self.db.unchecked_transaction()?;
+        let result = f(&tx)?;
+        tx.commit()?;
+        Ok(result)
+    }
+}
+
+// Walks series through the database, feeding untranslated lines to a backend
+pub struct Driver<'a, T> {
+    tl: T,
+    roster: &'a Roster,
+    memory: &'a Memory,
+    examples: &'a Examples,
+    normalizer: Normalizer,
+    recovery: Recovery,
+    nbest: NBest<'a>,
+    // how many series may run at once, each on its own server slot
+    pub slots: usize
+}
+
+impl<'a> Driver<'a, Backend<'a>> {
+    pub fn new(config: &Config, roster: &'a Roster, glossary: &'a Glossary, memory: &'a Memory, examples: &'a Examples) -> anyhow::Result<Self> {
+        Ok(Self {
+            tl: Backend::new(config, roster, glossary)?,
+            roster,
+            memory,
+            examples,
+            normalizer: Normalizer::new(config, true)?,
+            recovery: Recovery::new(config),
+            nbest: NBest::new(config, glossary),
+            // the terminal can only take one question at a time
+            slots: match config.backend {
+                config::Backend::Manual => 1,
+                _ => config.server.slots.max(1)
+            }
+        })
+    }
+}
+
+impl<T: Translator> Driver<'_, T> {
+    fn decode(&self, speaker: Option<String>) -> anyhow::Result<Option<(String, String)>> {
+        speaker.map(|speaker| {
+            let decoded = self.roster.decode(&speaker)?.to_string();
+            Ok((speaker, decoded))
+        }).transpose()
+    }
+
+    // Translates `nodes` in order, following on from `seen`, which is left as context for whatever
+    // comes next
+    pub async fn run(&self, db: &Connection, writer: &Writer<'_>, nodes: impl IntoIterator<Item = &(u16, String)>, seen: &mut Vec<Seen>, slot: usize) -> anyhow::Result<()> {
+        let session = Session::new(slot);
+
+        for &(scriptid, ref thread) in nodes {
+            eprintln!("\n--------- {scriptid}:{thread} ---------");
+            // read the whole node before awaiting anything, as other series use the connection too
+            let rows = db.prepare_cached("
+                SELECT address, speaker, body, variant_body, tl_body
+                FROM dialogue LEFT NATURAL JOIN dialogueTl
+                WHERE scriptid = ? and thread = ?")?
+                .query_map((scriptid, thread), |row| row.try_into())?
+                .collect::<Result<Vec<(u32, Option<String>, String, Option<String>, Option<String>)>, _>>()?;
+
+            for (address, speaker, source, source_variant, translation) in rows {
+                let speaker = self.decode(speaker.map(|s| self.normalizer.speaker(&s)))?;
+                let line = self.normalizer.body(&source);
+                let line_variant = source_variant.as_deref().map(|v| self.normalizer.body(v));
+
+                if let Some(translation) = translation {
+                    seen.push(Seen {
+                        speaker,
+                        jpline: line,
+                        enline: self.normalizer.translation(&translation)
+                    });
+                    continue;
+                }
+
+                eprintln!("address = {address:X}");
+                let speaker_prefix = speaker.as_ref().map_or(String::new(), |(_, en)| format!("[{en}]: "));
+                let jpspeaker = speaker.as_ref().map(|(jp, _)| jp.as_str());
+                let remembered = self.memory.find(jpspeaker, &line);
+
+                // a line said just the same before can be taken as it was then, unless it has a
+                // variant to keep in step with
+                if self.memory.mode == MemoryMode::Reuse && source_variant.is_none()
+                    && let Some(ref m) = remembered
+                    && let Ok(restored) = self.normalizer.restore(&source, &m.text)
+                {
+                    eprintln!("{speaker_prefix}{}\n(from memory, {}:{:X})\n", m.text, m.scriptid, m.address);
+                    writer.write(|tx| {
+                        tx.prepare_cached("
+                            INSERT OR REPLACE INTO dialogueTl(scriptid, address, tl_body, tl_variant_body, reviewed)
+                            VALUES (?, ?, ?, NULL, 0)")?
+                            .execute((scriptid, address, &restored))?;
+                        tx.prepare_cached("
+                            INSERT OR REPLACE INTO dialogueTlMemory(scriptid, address, source_scriptid, source_address, reused)
+                            VALUES (?, ?, ?, ?, 1)")?
+                            .execute((scriptid, address, m.scriptid, m.address))?;
+                        tx.prepare_cached("DELETE FROM dialogueTlCandidates WHERE scriptid = ? AND address = ?")?
+                            .execute((scriptid, address))?;
+                        Ok(())
+                    })?;
+                    seen.push(Seen {
+                        speaker,
+                        jpline: line,
+                        enline: m.text.clone()
+                    });
+                    self.examples.learn(seen.last().unwrap());
+                    continue;
+                }
+
+                let (translation_variant, strategy_variant) = match line_variant {
+                    // translate the variant in a vacuum
+                    Some(ref line) => {
+                        let (tl, strategy) = self.recovery.translate(&self.tl, &mut seen.clone(), &Request {
+                            session: &session,
+                            scriptid,
+                            address,
+                            speaker: speaker.as_ref(),
+                            line,
+                            n_predict: None,
+                            seed: None,
+                            logprobs: false,
+                            hint: None,
+                            examples: &[]
+                        }).await?;
+                        (Some(tl), Some(strategy))
+                    },
+                    None => (None, None)
+                };
+
+                let examples = self.examples.find(&line, seen);
+                let candidates = self.nbest.translate(&self.recovery, &self.tl, seen, &Request {
+                    session: &session,
+                    scriptid,
+                    address,
+                    speaker: speaker.as_ref(),
+                    line: &line,
+                    n_predict: None,
+                    seed: None,
+                    logprobs: self.memory.wants_logprobs(),
+                    hint: remembered.as_ref().map(|m| m.text.as_str()),
+                    examples: &examples
+                }).await?;
+
+                // a candidate whose placeholders can't be put back is no use
+                let mut restored = candidates.iter()
+                    .map(|c| self.normalizer.restore(&source, &c.translation.text))
+                    .collect::<Vec<_>>();
+                let Some(picked) = nbest::pick(&candidates, |i| restored[i].is_ok()) else {
+                    return Err(restored.swap_remove(0).unwrap_err()).with_context(|| format!("{scriptid}:{address:X}"));
+                };
+
+                if self.nbest.enabled() {
+                    for (i, c) in candidates.iter().enumerate() {
+                        let mark = if i == picked { '*' } else { ' ' };
+                        eprintln!("{mark}{i} ({:.3}) {speaker_prefix}{}", c.score.unwrap_or(f64::NAN), c.translation.text);
+                    }
+                    eprintln!();
+                }
+                let Candidate { translation: Translation { text: ref translation, ref params, logprob }, strategy, .. } = candidates[picked];
+                eprintln!("{speaker_prefix}{translation}\n");
+                if strategy != Strategy::Direct {
+                    eprintln!("({strategy})\n");
+                }
+
+                if let Some(ref variant) = translation_variant {
+                    eprintln!("[VARIANT] {speaker_prefix}{}\n", variant.text);
+                }
+
+                let restored_main = restored[picked].as_ref().unwrap();
+                let restored_variant = translation_variant.as_ref()
+                    .map(|v| self.normalizer.restore(source_variant.as_deref().unwrap(), &v.text))
+                    .transpose().with_context(|| format!("{scriptid}:{address:X} (variant)"))?;
+
+                writer.write(|tx| {
+                    tx.prepare_cached("
+                        INSERT OR REPLACE INTO dialogueTl(scriptid, address, tl_body, tl_variant_body, reviewed)
+                        VALUES (?, ?, ?, ?, ?)")?
+                        .execute((scriptid, address, restored_main, &restored_variant, params["backend"] == "manual"))?;
+                    tx.prepare_cached("
+                        INSERT OR REPLACE INTO dialogueTlMeta(scriptid, address, strategy, n_predict, pieces, params, variant_strategy, variant_params, logprob)
+                        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)")?
+                        .execute((
+                            scriptid, address,
+                            strategy.name(), strategy.n_predict(), strategy.pieces(), params.to_string(),
+                            strategy_variant.map(Strategy::name), translation_variant.as_ref().map(|v| v.params.to_string()),
+                            logprob
+                        ))?;
+                    if let Some(ref m) = remembered {
+                        tx.prepare_cached("
+                            INSERT OR REPLACE INTO dialogueTlMemory(scriptid, address, source_scriptid, source_address, reused)
+                            VALUES (?, ?, ?, ?, 0)")?
+                            .execute((scriptid, address, m.scriptid, m.address))?;
+                    }
+
+                    // whatever was kept from an earlier translation of the line is out of date
+                    tx.prepare_cached("DELETE FROM dialogueTlCandidates WHERE scriptid = ? AND address = ?")?
+                        .execute((scriptid, address))?;
+                    if self.nbest.enabled() {
+                        for (i, (c, restored)) in candidates.iter().zip(&restored).enumerate() {
+                            let Ok(restored) = restored else {
+                                continue;
+                            };
+                            tx.prepare_cached("
+                                INSERT INTO dialogueTlCandidates(scriptid, address, idx, tl_body, score, logprob, strategy, n_predict, pieces, params)
+                                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")?
+                                .execute((
+                                    scriptid, address, i, restored, c.score, c.translation.logprob,
+                                    c.strategy.name(), c.strategy.n_predict(), c.strategy.pieces(), c.translation.params.to_string()
+                                ))?;
+                        }
+                    }
+                    Ok(())
+                })?;
+
+                self.memory.learn(jpspeaker, &line, &candidates[picked].translation, scriptid, address);
+                seen.push(Seen {
+                    speaker,
+                    jpline: line,
+                    enline: translation.clone()
+                });
+                self.examples.learn(seen.last().unwrap());
+            }
+        }
+
+        if session.tokens.get() > 0 {
+            eprintln!("prompt cache: {session}");
+        }
+
+        Ok(())
+    }
 }
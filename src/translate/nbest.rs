@@ -0,0 +1,96 @@
+use crate::config::{Config, Scorer};
+
+use super::{glossary::Glossary, recovery::{Recovery, Strategy}, Request, Seen, Translation, Translator};
+
+pub struct Candidate {
+    pub translation: Translation,
+    pub strategy: Strategy,
+    // higher is better; unknown sorts last
+    pub score: Option<f64>
+}
+
+// Asks for several translations of a line, each with its own seed
+#[derive(Debug)]
+pub struct NBest<'a> {
+    n: usize,
+    seed: Option<u32>,
+    scorer: Scorer,
+    length_ratio: f64,
+    glossary: &'a Glossary
+}
+
+impl<'a> NBest<'a> {
+    pub fn new(config: &Config, glossary: &'a Glossary) -> Self {
+        Self {
+            n: config.candidates.n.max(1),
+            seed: config.sampling.seed,
+            scorer: config.candidates.scorer,
+            length_ratio: config.candidates.length_ratio,
+            glossary
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.n > 1
+    }
+
+    fn score(&self, line: &str, tl: &Translation) -> Option<f64> {
+        match self.scorer {
+            Scorer::Logprob => tl.logprob,
+            Scorer::Glossary => {
+                let text = tl.text.to_lowercase();
+                let (used, found) = self.glossary.iter()
+                    .filter(|t| line.contains(&t.source))
+                    .fold((0, 0), |(used, found), t| (used + 1, found + text.contains(&t.target.to_lowercase()) as usize));
+                Some(if used == 0 { 1.0 } else { found as f64 / used as f64 })
+            },
+            Scorer::Length => {
+                let ratio = tl.text.chars().count() as f64 / line.chars().count().max(1) as f64;
+                Some(-(ratio / self.length_ratio).ln().abs())
+            }
+        }
+    }
+
+    // Every candidate that could be generated, in order. The first may trim `seen` like any other
+    // translation; the rest see what it left. Fails only if none could be.
+    pub async fn translate<T: Translator>(&self, recovery: &Recovery, tl: &T, seen: &mut Vec<Seen>, req: &Request<'_>) -> anyhow::Result<Vec<Candidate>> {
+        let mut candidates = Vec::with_capacity(self.n);
+        let mut first_error = None;
+        for i in 0..self.n {
+            let req = Request {
+                seed: if i == 0 { req.seed } else { self.seed.map(|s| s.wrapping_add(i as u32)) },
+                logprobs: req.logprobs || self.enabled(),
+                ..*req
+            };
+            let result = if i == 0 {
+                recovery.translate(tl, seen, &req).await
+            } else {
+                recovery.translate(tl, &mut seen.clone(), &req).await
+            };
+            match result {
+                Ok((translation, strategy)) => candidates.push(Candidate {
+                    score: self.score(req.line, &translation),
+                    translation,
+                    strategy
+                }),
+                Err(e) if self.enabled() => {
+                    eprintln!("candidate {i} failed: {e:#}");
+                    first_error.get_or_insert(e);
+                },
+                Err(e) => return Err(e)
+            }
+        }
+
+        match first_error {
+            Some(e) if candidates.is_empty() => Err(e),
+            _ => Ok(candidates)
+        }
+    }
+}
+
+// The best of the candidates `usable` allows, preferring earlier ones on ties
+pub fn pick(candidates: &[Candidate], usable: impl Fn(usize) -> bool) -> Option<usize> {
+    let score = |i: usize| candidates[i].score.unwrap_or(f64::NEG_INFINITY);
+    (0..candidates.len()).filter(|&i| usable(i))
+        .reduce(|best, i| if score(i) > score(best) { i } else { best })
+}
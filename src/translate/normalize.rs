@@ -0,0 +1,164 @@
+use std::{borrow::Cow, cell::Cell, fmt::Display};
+
+use anyhow::Context;
+use indexmap::IndexMap;
+use regex::Regex;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::config::{Config, Field, Placeholder, RuleKind, UnicodeForm};
+
+// Turns script text into what the model (and the speaker decoder) sees, and model output back
+// into script text.
+#[derive(Clone, Debug)]
+pub struct Normalizer {
+    rules: Box<[Rule]>,
+    placeholders: IndexMap<String, Placeholder>,
+    // print every rule that changes something
+    verbose: bool
+}
+
+#[derive(Clone, Debug)]
+struct Rule {
+    name: String,
+    field: Field,
+    kind: Kind,
+    fired: Cell<u64>
+}
+
+#[derive(Clone, Debug)]
+enum Kind {
+    Exact(String, String),
+    Regex(Regex, String),
+    Unicode(UnicodeForm)
+}
+
+impl Rule {
+    fn apply<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        match &self.kind {
+            Kind::Exact(from, to) if s == from => Cow::Owned(to.clone()),
+            Kind::Exact(..) => Cow::Borrowed(s),
+            Kind::Regex(re, to) => re.replace_all(s, to.as_str()),
+            Kind::Unicode(UnicodeForm::Nfc) => Cow::Owned(s.nfc().collect()),
+            Kind::Unicode(UnicodeForm::Nfd) => Cow::Owned(s.nfd().collect()),
+            Kind::Unicode(UnicodeForm::Nfkc) => Cow::Owned(s.nfkc().collect()),
+            Kind::Unicode(UnicodeForm::Nfkd) => Cow::Owned(s.nfkd().collect())
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PlaceholderMismatchError {
+    pub placeholder: String,
+    pub expected: usize,
+    pub found: usize
+}
+
+impl Display for PlaceholderMismatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "expected {} {} in translation, found {}", self.expected, self.placeholder, self.found)
+    }
+}
+
+impl std::error::Error for PlaceholderMismatchError {}
+
+impl Normalizer {
+    pub fn new(config: &Config, verbose: bool) -> anyhow::Result<Self> {
+        let rules = config.normalize.iter().enumerate().map(|(i, r)| {
+            let kind = match &r.kind {
+                RuleKind::Exact { from, to } => Kind::Exact(from.clone(), to.clone()),
+                RuleKind::Regex { from, to } => Kind::Regex(
+                    Regex::new(from).with_context(|| format!("normalize rule {}", i + 1))?,
+                    to.clone()
+                ),
+                RuleKind::Unicode { form } => Kind::Unicode(*form)
+            };
+            Ok(Rule {
+                name: r.name.clone().unwrap_or_else(|| format!("#{}", i + 1)),
+                field: r.field,
+                kind,
+                fired: Cell::new(0)
+            })
+        }).collect::<anyhow::Result<_>>()?;
+
+        Ok(Self { rules, placeholders: config.placeholders.clone(), verbose })
+    }
+
+    fn apply_rules(&self, field: Field, s: &str) -> String {
+        let mut s = s.to_owned();
+        for rule in self.rules.iter().filter(|r| r.field == field || r.field == Field::Both) {
+            if let Cow::Owned(new) = rule.apply(&s) && new != s {
+                if self.verbose {
+                    eprintln!("normalize {}: {s:?} -> {new:?}", rule.name);
+                }
+                rule.fired.set(rule.fired.get() + 1);
+                s = new;
+            }
+        }
+        s
+    }
+
+    fn substitute_placeholders(&self, s: &str) -> String {
+        self.placeholders.iter().fold(s.to_owned(), |s, (code, p)| s.replace(code, &p.source))
+    }
+
+    pub fn speaker(&self, speaker: &str) -> String {
+        self.substitute_placeholders(&self.apply_rules(Field::Speaker, speaker))
+    }
+
+    pub fn body(&self, body: &str) -> String {
+        self.substitute_placeholders(&self.apply_rules(Field::Body, body))
+    }
+
+    // How often each rule has changed something so far
+    pub fn fired(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.rules.iter().map(|r| (r.name.as_str(), r.fired.get()))
+    }
+
+    // A stored translation as the model should see it in history
+    pub fn translation(&self, translation: &str) -> String {
+        self.placeholders.iter().fold(translation.to_owned(), |s, (code, p)| s.replace(code, &p.target))
+    }
+
+    // Inverse of `translation`: put back the control codes that `source` (the raw script line)
+    // contains, in their original order. Every code has to be accounted for.
+    pub fn restore(&self, source: &str, translation: &str) -> anyhow::Result<String> {
+        let mut codes = self.placeholders.iter()
+            .flat_map(|(code, p)| source.match_indices(code.as_str()).map(move |(i, _)| (i, code, p)))
+            .collect::<Vec<_>>();
+        if codes.is_empty() {
+            return Ok(translation.to_owned());
+        }
+        codes.sort_by_key(|&(i, ..)| i);
+
+        // codes sharing a display name are matched up by position
+        let mut by_target = IndexMap::<&str, Vec<&str>>::new();
+        for &(_, code, p) in &codes {
+            by_target.entry(p.target.as_str()).or_default().push(code);
+        }
+
+        let mut restored = translation.to_owned();
+        for (target, codes) in by_target {
+            let found = restored.matches(target).count();
+            if found != codes.len() {
+                return Err(PlaceholderMismatchError {
+                    placeholder: target.to_owned(),
+                    expected: codes.len(),
+                    found
+                }.into());
+            }
+
+            let mut out = String::with_capacity(restored.len());
+            let mut rest = restored.as_str();
+            for code in codes {
+                let i = rest.find(target).unwrap();
+                out.push_str(&rest[..i]);
+                out.push_str(code);
+                rest = &rest[i + target.len()..];
+            }
+            out.push_str(rest);
+            restored = out;
+        }
+
+        Ok(restored)
+    }
+}
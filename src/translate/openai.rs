@@ -0,0 +1,139 @@
+use anyhow::Context;
+use reqwest::Client;
+use serde_json::json;
+
+use crate::config::{Config, Sampling};
+
+use super::{characters::Roster, llm, glossary::Glossary, http::{self, Retry}, metadata::{example, metadata}, MaxTokensReachedError, Request, Seen, Translation, Translator};
+
+// Any OpenAI-compatible /chat/completions endpoint (vLLM, llama.cpp's /v1, ...)
+#[derive(Debug)]
+pub struct OpenAi<'a> {
+    client: Client,
+    retry: Retry,
+    roster: &'a Roster,
+    glossary: &'a Glossary,
+    endpoint: String,
+    model: Option<String>,
+    api_key: Option<String>,
+    system_prompt: String,
+    max_history: usize,
+    n_predict: usize,
+    sampling: Sampling
+}
+
+impl<'a> OpenAi<'a> {
+    pub fn new(config: &Config, roster: &'a Roster, glossary: &'a Glossary) -> anyhow::Result<Self> {
+        let api_key = config.openai.api_key_env.as_deref()
+            .map(|var| std::env::var(var).with_context(|| format!("{var} is not set")))
+            .transpose()?;
+
+        Ok(Self {
+            client: http::client(config)?,
+            retry: Retry::new(&config.retry),
+            roster,
+            glossary,
+            endpoint: config.openai.endpoint.trim_end_matches('/').to_owned(),
+            model: config.openai.model.clone(),
+            api_key,
+            system_prompt: config.openai.system_prompt.clone(),
+            max_history: config.openai.max_history,
+            n_predict: config.context.n_predict,
+            sampling: config.sampling.clone()
+        })
+    }
+
+    // Same structure as the llama.cpp prompt: metadata up front, then one user/assistant pair per
+    // line of history, then the line to translate. Everything besides the lines goes in the one
+    // system message, as many chat templates want the roles to alternate after it.
+    fn build_messages(&self, seen: &[Seen], req: &Request<'_>) -> anyhow::Result<Vec<serde_json::Value>> {
+        let jpspeaker = req.speaker.map(|(jp, _)| jp.as_str());
+
+        let mut system = self.system_prompt.clone();
+        let examples = req.examples.iter().map(example);
+        for m in metadata(self.roster, self.glossary, seen, jpspeaker, req.line)?.into_iter().chain(examples) {
+            system.push('\n');
+            system.push_str(&m);
+        }
+        if let Some(hint) = req.hint {
+            system.push_str(&format!("\nThe next line has been translated before as: {hint}"));
+        }
+
+        let mut messages = vec![json!({ "role": "system", "content": system })];
+        for s in seen {
+            messages.push(json!({ "role": "user", "content": with_speaker(s.speaker.as_ref().map(|(jp, _)| jp.as_str()), &s.jpline) }));
+            messages.push(json!({ "role": "assistant", "content": with_speaker(s.speaker.as_ref().map(|(_, en)| en.as_str()), &s.enline) }));
+        }
+        messages.push(json!({ "role": "user", "content": with_speaker(jpspeaker, req.line) }));
+
+        Ok(messages)
+    }
+}
+
+fn with_speaker(speaker: Option<&str>, line: &str) -> String {
+    match speaker {
+        Some(speaker) => format!("[{speaker}]: {line}"),
+        None => line.to_owned()
+    }
+}
+
+impl Translator for OpenAi<'_> {
+    async fn translate(&self, seen: &mut Vec<Seen>, req: &Request<'_>) -> anyhow::Result<Translation> {
+        if seen.len() > self.max_history {
+            seen.drain(0..seen.len() - self.max_history);
+        }
+
+        let mut body = serde_json::to_value(&self.sampling)?;
+        body["messages"] = json!(self.build_messages(seen, req)?);
+        body["max_tokens"] = json!(req.n_predict.unwrap_or(self.n_predict));
+        if let Some(ref model) = self.model {
+            body["model"] = json!(model);
+        }
+        if let Some(seed) = req.seed {
+            body["seed"] = json!(seed);
+        }
+        if req.logprobs {
+            body["logprobs"] = json!(true);
+        }
+
+        let resp = self.retry.run(format_args!("{}:{:X} chat completion", req.scriptid, req.address), || {
+            let mut post = self.client.post(format!("{}/chat/completions", self.endpoint)).json(&body);
+            if let Some(ref key) = self.api_key {
+                post = post.bearer_auth(key);
+            }
+            http::send_json(post)
+        }).await?;
+
+        let content = resp
+            .pointer("/choices/0/message/content").context("no content")?
+            .as_str().context("content is not string")?;
+
+        let finish_reason = resp
+            .pointer("/choices/0/finish_reason").context("no finish reason")?
+            .as_str().context("finish reason is not str")?;
+
+        if finish_reason == "length" {
+            return Err(MaxTokensReachedError(content.to_owned()).into());
+        }
+
+        let logprobs = resp.pointer("/choices/0/logprobs/content").and_then(|c| c.as_array()).into_iter().flatten()
+            .filter_map(|t| t.pointer("/logprob")?.as_f64())
+            .collect::<Vec<_>>();
+
+        let mut params = json!({ "backend": "openai", "request": body });
+        params["request"].as_object_mut().unwrap().remove("messages");
+        for key in ["model", "system_fingerprint"] {
+            if let Some(value) = resp.get(key) {
+                params["server"][key] = value.clone();
+            }
+        }
+
+        // the model is asked to echo the speaker like the history does, but may not
+        let speaker_prefix = req.speaker.map_or(String::new(), |(_, en)| format!("[{en}]: "));
+        Ok(Translation {
+            text: content.trim().strip_prefix(speaker_prefix.as_str()).unwrap_or(content.trim()).trim().to_owned(),
+            params,
+            logprob: llm::mean(&logprobs)
+        })
+    }
+}
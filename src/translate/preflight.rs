@@ -0,0 +1,66 @@
+use std::collections::HashMap;
+
+use indexmap::IndexMap;
+use rusqlite::Connection;
+
+use crate::config::Config;
+
+use super::{normalize::Normalizer, Roster};
+
+const SAMPLES: usize = 3;
+
+#[derive(Debug, Default)]
+pub struct Unresolved {
+    pub lines: u64,
+    pub samples: Vec<(u16, u32, String)>
+}
+
+// Every speaker in the script that the roster can't decode, keyed by its normalized form
+pub fn scan(config: &Config, db: &Connection, roster: &Roster) -> anyhow::Result<IndexMap<String, Unresolved>> {
+    let normalizer = Normalizer::new(config, false)?;
+
+    let mut stmt = db.prepare("
+        SELECT scriptid, address, speaker, body
+        FROM dialogue
+        WHERE speaker IS NOT NULL
+        ORDER BY scriptid, address")?;
+
+    let mut speakers = IndexMap::<String, Unresolved>::new();
+    // each distinct speaker is normalized once, so the rules' counts are of speakers
+    let mut normalized = HashMap::<String, String>::new();
+    let mut rows = stmt.query(())?;
+    while let Some(row) = rows.next()? {
+        let (scriptid, address, speaker, body) = <(u16, u32, String, String)>::try_from(row)?;
+        let speaker = normalized.entry(speaker).or_insert_with_key(|s| normalizer.speaker(s));
+        let u = speakers.entry(speaker.clone()).or_default();
+        u.lines += 1;
+        if u.samples.len() < SAMPLES {
+            u.samples.push((scriptid, address, body));
+        }
+    }
+
+    speakers.retain(|speaker, _| roster.decode(speaker).is_err());
+
+    for (rule, n) in normalizer.fired() {
+        if n > 0 {
+            eprintln!("preflight: normalize {rule} fired on {n} speakers");
+        }
+    }
+
+    Ok(speakers)
+}
+
+pub fn report(unresolved: &IndexMap<String, Unresolved>) {
+    if unresolved.is_empty() {
+        eprintln!("preflight: all speakers resolved");
+        return;
+    }
+
+    eprintln!("preflight: {} unresolved speakers", unresolved.len());
+    for (speaker, u) in unresolved {
+        eprintln!("  {speaker} ({} lines)", u.lines);
+        for (scriptid, address, body) in &u.samples {
+            eprintln!("    {scriptid}:{address:X} {body}");
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use std::fmt::Display;
+
+use crate::config::Config;
+
+use super::{MaxTokensReachedError, PromptTooLongError, Request, RunawayError, Seen, Translation, Translator};
+
+// Sentence endings a long line can be broken after
+const BOUNDARIES: &[char] = &['。', '！', '？', '」'];
+
+// How the stored translation came about
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Strategy {
+    // the first attempt fit
+    Direct,
+    // a larger budget than configured was needed
+    Budget(usize),
+    // translated sentence by sentence and joined
+    Split(usize)
+}
+
+impl Strategy {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Direct => "direct",
+            Self::Budget(_) => "budget",
+            Self::Split(_) => "split"
+        }
+    }
+
+    pub fn n_predict(self) -> Option<usize> {
+        match self {
+            Self::Budget(n) => Some(n),
+            _ => None
+        }
+    }
+
+    pub fn pieces(self) -> Option<usize> {
+        match self {
+            Self::Split(n) => Some(n),
+            _ => None
+        }
+    }
+}
+
+impl Display for Strategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Direct => f.write_str("direct"),
+            Self::Budget(n) => write!(f, "n_predict={n}"),
+            Self::Split(n) => write!(f, "split into {n}")
+        }
+    }
+}
+
+// Breaks a line after each run of sentence endings, keeping the endings with their sentence
+pub fn split_sentences(line: &str) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, c)| c);
+        if BOUNDARIES.contains(&c) && next.is_none_or(|c| !BOUNDARIES.contains(&c)) {
+            let end = i + c.len_utf8();
+            if !line[end..].trim().is_empty() {
+                pieces.push(&line[start..end]);
+                start = end;
+            }
+        }
+    }
+    pieces.push(&line[start..]);
+    pieces
+}
+
+// The model said too much, whether it ran out of budget or was stopped
+fn overran(e: &anyhow::Error) -> bool {
+    e.is::<MaxTokensReachedError>() || e.is::<RunawayError>()
+}
+
+#[derive(Clone, Debug)]
+pub struct Recovery {
+    n_predict: Box<[usize]>,
+    split: bool
+}
+
+impl Recovery {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            n_predict: config.recovery.n_predict.iter().copied().filter(|&n| n > config.context.n_predict).collect(),
+            split: config.recovery.split
+        }
+    }
+
+    // Translates `req`, falling back to larger budgets and then to splitting whenever the model
+    // runs out of tokens or runs away. Any other error is passed straight through.
+    pub async fn translate<T: Translator>(&self, tl: &T, seen: &mut Vec<Seen>, req: &Request<'_>) -> anyhow::Result<(Translation, Strategy)> {
+        let mut last = match tl.translate(seen, req).await {
+            Ok(tl) => return Ok((tl, Strategy::Direct)),
+            Err(e) if overran(&e) => e,
+            Err(e) => return Err(e)
+        };
+
+        // the largest budget that still left room for the prompt
+        let mut fits = None;
+        for &n_predict in &self.n_predict {
+            eprintln!("{last}; retrying with n_predict={n_predict}");
+            match tl.translate(seen, &Request { n_predict: Some(n_predict), ..*req }).await {
+                Ok(tl) => return Ok((tl, Strategy::Budget(n_predict))),
+                Err(e) if overran(&e) => {
+                    last = e;
+                    fits = Some(n_predict);
+                },
+                // larger budgets won't fit either
+                Err(e) if e.is::<PromptTooLongError>() => {
+                    eprintln!("{e}");
+                    break;
+                },
+                Err(e) => return Err(e)
+            }
+        }
+
+        let pieces = split_sentences(req.line);
+        if !self.split || pieces.len() < 2 {
+            return Err(last);
+        }
+
+        eprintln!("{last}; splitting into {} pieces", pieces.len());
+        // each piece sees the ones before it, as if they were lines of their own
+        let mut context = seen.clone();
+        let mut translated = Vec::with_capacity(pieces.len());
+        let mut params = serde_json::Value::Null;
+        let mut logprobs = Vec::new();
+        for line in &pieces {
+            let tl = tl.translate(&mut context, &Request {
+                line,
+                n_predict: fits,
+                // the hint is for the whole line
+                hint: None,
+                ..*req
+            }).await?;
+            context.push(Seen {
+                speaker: req.speaker.cloned(),
+                jpline: (*line).to_owned(),
+                enline: tl.text.clone()
+            });
+            translated.push(tl.text);
+            logprobs.extend(tl.logprob);
+            // every piece is asked for the same way
+            params = tl.params;
+        }
+
+        let logprob = (logprobs.len() == pieces.len()).then(|| logprobs.iter().sum::<f64>() / logprobs.len() as f64);
+        Ok((Translation { text: translated.join(" "), params, logprob }, Strategy::Split(pieces.len())))
+    }
+}
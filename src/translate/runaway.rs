@@ -0,0 +1,56 @@
+use crate::config::Config;
+
+// Notices generations that have gone off the rails, so they can be cut short instead of running
+// to the end of their budget
+#[derive(Clone, Debug)]
+pub struct Runaway {
+    repeats: usize,
+    min_span: usize,
+    japanese: bool
+}
+
+// Kana, kanji and half-width katakana
+pub const JAPANESE: [(char, char); 4] = [
+    ('\u{3040}', '\u{30ff}'),
+    ('\u{3400}', '\u{4dbf}'),
+    ('\u{4e00}', '\u{9fff}'),
+    ('\u{ff66}', '\u{ff9f}')
+];
+
+fn is_japanese(c: char) -> bool {
+    JAPANESE.iter().any(|&(lo, hi)| (lo..=hi).contains(&c))
+}
+
+impl Runaway {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            repeats: config.runaway.repeats,
+            min_span: config.runaway.min_span,
+            japanese: config.runaway.japanese
+        }
+    }
+
+    // Why `output` (everything generated so far) should be abandoned, if it should
+    pub fn check(&self, output: &str) -> Option<String> {
+        if self.japanese && let Some(c) = output.chars().find(|&c| is_japanese(c)) {
+            return Some(format!("Japanese in the output ({c:?})"));
+        }
+
+        if self.repeats >= 2 {
+            let chars = output.chars().collect::<Vec<_>>();
+            // the output ends with some unit said `repeats` times over
+            for unit in 1..=chars.len() / self.repeats {
+                let span = unit * self.repeats;
+                if span < self.min_span {
+                    continue;
+                }
+                let tail = &chars[chars.len() - span..];
+                if tail.chunks(unit).all(|c| c == &tail[..unit]) {
+                    return Some(format!("{:?} repeated {} times", tail[..unit].iter().collect::<String>(), self.repeats));
+                }
+            }
+        }
+
+        None
+    }
+}
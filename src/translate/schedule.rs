@@ -0,0 +1,82 @@
+use futures_util::{stream::FuturesUnordered, StreamExt};
+use rusqlite::Connection;
+
+use crate::graph::{Tree, ROOT};
+
+use super::{Driver, Seen, Translator, Writer};
+
+// A subtree to translate, and the context its parent left
+struct Job {
+    node: u32,
+    seen: Vec<Seen>,
+    // the slot that already has the parent's prompt cached
+    slot: Option<usize>,
+    // further tries if the run fails, once counted
+    retries: Option<usize>
+}
+
+// Translates the whole tree depth first, each node once. A run follows the tree until it branches;
+// each branch then starts from its own copy of the context at that point. Branches are independent
+// of each other, so up to as many run at once as the driver has slots.
+// A run that fails is tried again from the same context, as many times as there are leaves below
+// it beyond the first, as each of them would have been a series of its own trying the same lines.
+pub async fn run<T: Translator>(driver: &Driver<'_, T>, db: &Connection, tree: &Tree) {
+    let writer = Writer::new(db);
+
+    // last in, first out, so the tree is finished one subtree at a time
+    let mut stack = tree.branches(ROOT).into_iter().rev()
+        .map(|node| Job { node, seen: Vec::new(), slot: None, retries: None })
+        .collect::<Vec<_>>();
+    let mut free = vec![true; driver.slots];
+    let mut running = FuturesUnordered::new();
+
+    loop {
+        while let Some(any) = free.iter().position(|&f| f) && let Some(job) = stack.pop() {
+            let slot = job.slot.filter(|&s| free[s]).unwrap_or(any);
+            free[slot] = false;
+
+            let (chain, branches) = tree.chain(job.node);
+            for &v in &chain {
+                let (scriptid, thread) = tree.node(v);
+                eprint!("-> {scriptid}:{thread} ");
+            }
+            eprintln!("(slot {slot})");
+
+            let writer = &writer;
+            let mut seen = job.seen.clone();
+            let retry = Job { slot: Some(slot), ..job };
+            running.push(async move {
+                let result = driver.run(db, writer, chain.iter().map(|&v| tree.node(v)), &mut seen, slot).await;
+                (slot, retry, result.map(|()| (seen, branches)))
+            });
+        }
+
+        let Some((slot, retry, result)) = running.next().await else {
+            break;
+        };
+        free[slot] = true;
+        match result {
+            Ok((seen, branches)) => {
+                // each later branch starts from a copy of the context; the first takes it over, on
+                // the slot that has it cached
+                if let Some((&first, rest)) = branches.split_first() {
+                    stack.extend(rest.iter().rev().map(|&node| Job { node, seen: seen.clone(), slot: None, retries: None }));
+                    stack.push(Job { node: first, seen, slot: Some(slot), retries: None });
+                }
+            },
+            Err(e) => {
+                eprintln!("SERIES FAILED: {e:?}");
+                let retries = retry.retries.unwrap_or_else(|| tree.paths(retry.node).len() - 1);
+                let (scriptid, thread) = tree.node(retry.node);
+                if retries > 0 {
+                    eprintln!("trying {scriptid}:{thread} again ({retries} left)");
+                    stack.push(Job { retries: Some(retries - 1), ..retry });
+                } else {
+                    // what's below is left for another time
+                    eprintln!("GAVE UP on {scriptid}:{thread} and below: {} lines were untranslated", tree.below(retry.node));
+                }
+            }
+        }
+        eprintln!();
+    }
+}
@@ -0,0 +1,103 @@
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::config::Config;
+
+use super::Seen;
+
+// Ships with the binary; anything else is read from a file
+const BUILTIN: &[(&str, &str)] = &[
+    ("llama3", include_str!("templates/llama3.toml")),
+    ("chatml", include_str!("templates/chatml.toml")),
+    ("gemma", include_str!("templates/gemma.toml")),
+    ("mistral", include_str!("templates/mistral.toml"))
+];
+
+// How a model wants its prompt laid out. Each field is text with {placeholders}:
+// - header: {metadata}, the metadata lines each rendered with metadata_line ({line})
+// - speaker: {name}; empty when a line has no speaker
+// - source, target: {speaker} and {line}, for the Japanese and English halves of an exchange;
+//   source may also place the {hint}
+// - prompt: what follows the last source, where the model takes over
+// - hint: {line}, a trusted earlier translation of the line, shown where source puts {hint}, or
+//   else just before it; optional, and without it there are no hints
+// The header and every exchange are tokenized separately, so each must begin with a special token.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    header: String,
+    metadata_line: String,
+    speaker: String,
+    source: String,
+    target: String,
+    prompt: String,
+    #[serde(default)]
+    hint: Option<String>
+}
+
+// Substitutes {name}s in one pass, so braces in the values are left alone
+fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest.find('}')
+            .and_then(|end| values.iter().find(|(k, _)| *k == &rest[1..end]).map(|(_, v)| (end, v)));
+        match value {
+            Some((end, v)) => {
+                out.push_str(v);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                out.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+impl Template {
+    // A built-in by name, or a .toml file
+    pub fn load(config: &Config) -> anyhow::Result<Self> {
+        let name = &config.prompt.template;
+        let text = if name.ends_with(".toml") {
+            std::fs::read_to_string(name).with_context(|| format!("failed to read {name}"))?
+        } else {
+            let (_, text) = BUILTIN.iter().find(|(n, _)| n == name).with_context(|| format!(
+                "no built-in template {name:?} (there are {}); give a .toml file instead",
+                BUILTIN.iter().map(|(n, _)| *n).collect::<Vec<_>>().join(", ")))?;
+            text.to_string()
+        };
+        toml::from_str(&text).with_context(|| format!("invalid template {name}"))
+    }
+
+    pub fn header(&self, metadata: &[String]) -> String {
+        let lines = metadata.iter().map(|m| fill(&self.metadata_line, &[("line", m)])).collect::<String>();
+        fill(&self.header, &[("metadata", &lines)])
+    }
+
+    pub fn speaker(&self, name: Option<&str>) -> String {
+        name.map_or(String::new(), |name| fill(&self.speaker, &[("name", name)]))
+    }
+
+    // One translated line of history
+    pub fn exchange(&self, seen: &Seen) -> String {
+        let (jp, en) = seen.speaker.as_ref().map(|(jp, en)| (jp.as_str(), en.as_str())).unzip();
+        fill(&self.source, &[("speaker", &self.speaker(jp)), ("line", &seen.jpline), ("hint", "")])
+            + &fill(&self.target, &[("speaker", &self.speaker(en)), ("line", &seen.enline)])
+    }
+
+    // The line to translate, up to where the model takes over
+    pub fn tail(&self, speaker: Option<&str>, line: &str, hint: Option<&str>) -> String {
+        let hint = hint.zip(self.hint.as_deref()).map_or(String::new(), |(hint, t)| fill(t, &[("line", hint)]));
+        let speaker = self.speaker(speaker);
+        if self.source.contains("{hint}") {
+            fill(&self.source, &[("speaker", &speaker), ("line", line), ("hint", &hint)]) + &self.prompt
+        } else {
+            hint + &fill(&self.source, &[("speaker", &speaker), ("line", line)]) + &self.prompt
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use std::{fs::File, io::Read, path::Path};
+
+use anyhow::Context;
+use reqwest::Client;
+use serde_json::json;
+use tokenizers::{
+    models::bpe::{Vocab, BPE},
+    pre_tokenizers::{byte_level::ByteLevel, sequence::Sequence, split::{Split, SplitPattern}, PreTokenizerWrapper},
+    AddedToken, SplitDelimiterBehavior
+};
+
+use crate::config::Config;
+
+use super::http::{self, Retry};
+
+// Counts tokens either the way the server does or, given a tokenizer file, without it. Both parse
+// special tokens in the text and add none of their own, like llama.cpp's /tokenize.
+#[derive(Debug)]
+pub enum Tokenizer {
+    Server {
+        client: Client,
+        retry: Retry,
+        endpoint: String
+    },
+    Local(Box<tokenizers::Tokenizer>)
+}
+
+impl Tokenizer {
+    pub fn new(config: &Config) -> anyhow::Result<Self> {
+        Ok(match config.context.tokenizer {
+            Some(ref path) => Self::Local(Box::new(load(path)
+                .with_context(|| format!("failed to load tokenizer from {}", path.display()))?)),
+            None => Self::Server {
+                client: http::client(config)?,
+                retry: Retry::new(&config.retry),
+                endpoint: config.server.endpoint.trim_end_matches('/').to_owned()
+            }
+        })
+    }
+
+    // Tokens of each piece. Pieces must start and end on special tokens so that nothing merges
+    // across them; the server gets them all in one request. `what` names the request in the log.
+    pub async fn encode(&self, what: &str, pieces: &[&str]) -> anyhow::Result<Vec<Vec<u32>>> {
+        match self {
+            Self::Server { client, retry, endpoint } => {
+                let content = pieces.concat();
+                let tokens = retry.run(format_args!("{what} tokenize"), || tokenize(client, endpoint, &content)).await?;
+
+                let mut tokens = tokens.into_iter();
+                let encoded = pieces.iter().map(|piece| {
+                    let mut ids = Vec::new();
+                    let mut len = 0;
+                    while len < piece.len() {
+                        let (id, n) = tokens.next().context("tokenizer pieces do not add up")?;
+                        ids.push(id);
+                        len += n;
+                    }
+                    anyhow::ensure!(len == piece.len(), "token straddles a prompt boundary");
+                    Ok(ids)
+                }).collect::<anyhow::Result<_>>()?;
+                anyhow::ensure!(tokens.next().is_none(), "tokenizer pieces do not add up");
+
+                Ok(encoded)
+            },
+            Self::Local(tokenizer) => pieces.iter().map(|piece| {
+                Ok(tokenizer.encode_fast(*piece, false).map_err(anyhow::Error::from_boxed)?.get_ids().to_owned())
+            }).collect()
+        }
+    }
+}
+
+// (token, length of its piece in bytes)
+async fn tokenize(client: &Client, endpoint: &str, content: &str) -> anyhow::Result<Vec<(u32, usize)>> {
+    http::send_json(client
+        .post(format!("{endpoint}/tokenize"))
+        .json(&json!({ "content": content, "with_pieces": true }))).await?
+        .pointer("/tokens").context("no tokens")?
+        .as_array().context("tokens is not array")?
+        .iter().map(|t| {
+            let id = t.pointer("/id").and_then(|n| n.as_u64()).context("no token id")?.try_into()?;
+            // pieces that aren't valid UTF-8 come back as byte arrays
+            let len = match t.pointer("/piece").context("no piece")? {
+                serde_json::Value::String(s) => s.len(),
+                serde_json::Value::Array(bytes) => bytes.len(),
+                _ => anyhow::bail!("piece is not string or bytes")
+            };
+            Ok((id, len))
+        }).collect()
+}
+
+// An HF tokenizer.json, or the vocabulary embedded in a GGUF model
+fn load(path: &Path) -> anyhow::Result<tokenizers::Tokenizer> {
+    let mut magic = [0; 4];
+    File::open(path)?.read_exact(&mut magic)?;
+    if &magic == b"GGUF" {
+        from_gguf(path)
+    } else {
+        tokenizers::Tokenizer::from_file(path).map_err(anyhow::Error::from_boxed)
+    }
+}
+
+// Pre-tokenizer splits llama.cpp applies ahead of byte-level BPE, by `tokenizer.ggml.pre`
+fn pre_split(pre: &str) -> anyhow::Result<Option<&'static str>> {
+    Ok(match pre {
+        "default" | "gpt-2" => None,
+        "llama3" | "llama-v3" | "llama-bpe" | "smaug-bpe" => Some(r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}{1,3}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+"),
+        "qwen2" | "deepseek-r1-qwen" => Some(r"(?i:'s|'t|'re|'ve|'m|'ll|'d)|[^\r\n\p{L}\p{N}]?\p{L}+|\p{N}| ?[^\s\p{L}\p{N}]+[\r\n]*|\s*[\r\n]+|\s+(?!\S)|\s+"),
+        _ => anyhow::bail!("unsupported pre-tokenizer {pre:?}; use the model's tokenizer.json instead")
+    })
+}
+
+// Only byte-level BPE vocabularies ("gpt2", as llama.cpp calls them) are understood
+fn from_gguf(path: &Path) -> anyhow::Result<tokenizers::Tokenizer> {
+    let meta = gguf::read(path)?;
+    let str = |key: &str| match meta.get(key) {
+        Some(gguf::Value::Str(s)) => Ok(Some(s.as_str())),
+        None => Ok(None),
+        _ => Err(anyhow::anyhow!("{key} is not a string"))
+    };
+    let strs = |key: &str| match meta.get(key) {
+        Some(gguf::Value::Strs(s)) => Ok(s.as_slice()),
+        _ => Err(anyhow::anyhow!("{key} is missing or not a string array"))
+    };
+
+    let model = str("tokenizer.ggml.model")?.context("no tokenizer in this file")?;
+    anyhow::ensure!(model == "gpt2", "unsupported tokenizer model {model:?}; use the model's tokenizer.json instead");
+    let pre = pre_split(str("tokenizer.ggml.pre")?.unwrap_or("default"))?;
+
+    let tokens = strs("tokenizer.ggml.tokens")?;
+    let merges = strs("tokenizer.ggml.merges")?.iter()
+        .map(|m| m.split_once(' ').map(|(a, b)| (a.to_owned(), b.to_owned())).with_context(|| format!("bad merge {m:?}")))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let vocab = tokens.iter().enumerate()
+        .map(|(id, t)| Ok((t.clone(), id.try_into()?)))
+        .collect::<anyhow::Result<Vocab>>()?;
+
+    let bpe = BPE::builder().vocab_and_merges(vocab, merges).build().map_err(anyhow::Error::from_boxed)?;
+    let mut tokenizer = tokenizers::Tokenizer::new(bpe);
+    tokenizer.with_pre_tokenizer(Some(match pre {
+        Some(pattern) => PreTokenizerWrapper::Sequence(Sequence::new(vec![
+            Split::new(SplitPattern::Regex(pattern.into()), SplitDelimiterBehavior::Isolated, false)
+                .map_err(anyhow::Error::from_boxed)?.into(),
+            ByteLevel::new(false, false, false).into()
+        ])),
+        None => ByteLevel::new(false, false, true).into()
+    }));
+
+    // control (3) and user-defined (4) tokens are matched whole in the text
+    if let Some(gguf::Value::Ints(types)) = meta.get("tokenizer.ggml.token_type") {
+        let special = |ty| tokens.iter().zip(types).filter(move |&(_, &t)| t == ty).map(|(t, _)| t.clone());
+        tokenizer.add_special_tokens(special(3).map(|t| AddedToken::from(t, true))).map_err(anyhow::Error::from_boxed)?;
+        tokenizer.add_tokens(special(4).map(|t| AddedToken::from(t, false))).map_err(anyhow::Error::from_boxed)?;
+    }
+
+    Ok(tokenizer)
+}
+
+mod gguf {
+    use std::{collections::HashMap, fs::File, io::{BufReader, Read}, path::Path};
+
+    // The parts of GGUF metadata a tokenizer needs; everything else is skipped
+    pub enum Value {
+        Str(String),
+        Strs(Vec<String>),
+        Ints(Vec<i64>),
+        Other
+    }
+
+    const STRING: u32 = 8;
+    const ARRAY: u32 = 9;
+
+    fn scalar_size(ty: u32) -> anyhow::Result<u64> {
+        Ok(match ty {
+            0 | 1 | 7 => 1,
+            2 | 3 => 2,
+            4..=6 => 4,
+            10..=12 => 8,
+            _ => anyhow::bail!("unknown GGUF type {ty}")
+        })
+    }
+
+    fn u32(r: &mut impl Read) -> anyhow::Result<u32> {
+        let mut b = [0; 4];
+        r.read_exact(&mut b)?;
+        Ok(u32::from_le_bytes(b))
+    }
+
+    fn u64(r: &mut impl Read) -> anyhow::Result<u64> {
+        let mut b = [0; 8];
+        r.read_exact(&mut b)?;
+        Ok(u64::from_le_bytes(b))
+    }
+
+    fn string(r: &mut impl Read) -> anyhow::Result<String> {
+        let len = u64(r)?;
+        let mut s = Vec::new();
+        r.take(len).read_to_end(&mut s)?;
+        anyhow::ensure!(s.len() as u64 == len, "unexpected end of file");
+        Ok(String::from_utf8(s)?)
+    }
+
+    fn skip(r: &mut impl Read, n: u64) -> anyhow::Result<()> {
+        let skipped = std::io::copy(&mut r.take(n), &mut std::io::sink())?;
+        anyhow::ensure!(skipped == n, "unexpected end of file");
+        Ok(())
+    }
+
+    fn int(r: &mut impl Read, ty: u32) -> anyhow::Result<i64> {
+        let size = scalar_size(ty)?;
+        let mut b = [0; 8];
+        r.read_exact(&mut b[..size as usize])?;
+        Ok(match ty {
+            1 => b[0] as i8 as i64,
+            3 => i16::from_le_bytes([b[0], b[1]]) as i64,
+            5 => i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as i64,
+            11 => i64::from_le_bytes(b),
+            _ => u64::from_le_bytes(b) as i64
+        })
+    }
+
+    fn value(r: &mut impl Read, ty: u32) -> anyhow::Result<Value> {
+        match ty {
+            STRING => Ok(Value::Str(string(r)?)),
+            ARRAY => {
+                let ty = u32(r)?;
+                let len = u64(r)?;
+                match ty {
+                    STRING => Ok(Value::Strs((0..len).map(|_| string(r)).collect::<anyhow::Result<_>>()?)),
+                    // floats and bools are never wanted as integers
+                    0..=5 | 10 | 11 => Ok(Value::Ints((0..len).map(|_| int(r, ty)).collect::<anyhow::Result<_>>()?)),
+                    ARRAY => anyhow::bail!("nested GGUF arrays are not supported"),
+                    _ => {
+                        skip(r, len * scalar_size(ty)?)?;
+                        Ok(Value::Other)
+                    }
+                }
+            },
+            _ => {
+                skip(r, scalar_size(ty)?)?;
+                Ok(Value::Other)
+            }
+        }
+    }
+
+    // Reads the metadata key/value section, stopping before the tensor infos
+    pub fn read(path: &Path) -> anyhow::Result<HashMap<String, Value>> {
+        let mut r = BufReader::new(File::open(path)?);
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        anyhow::ensure!(&magic == b"GGUF", "not a GGUF file");
+        let version = u32(&mut r)?;
+        anyhow::ensure!(version >= 2, "GGUF version {version} is too old");
+
+        let _tensors = u64(&mut r)?;
+        let count = u64(&mut r)?;
+        (0..count).map(|_| {
+            let key = string(&mut r)?;
+            let ty = u32(&mut r)?;
+            Ok((key, value(&mut r, ty)?))
+        }).collect()
+    }
+}
@@ -0,0 +1,310 @@
+#![allow(dead_code)]
+
+use std::{collections::HashMap, net::SocketAddr, path::{Path, PathBuf}, process::{Command, Output}, sync::{mpsc, Arc, Mutex}};
+
+use futures_util::StreamExt;
+use axum::{body::Body, extract::State, http::StatusCode, response::{IntoResponse, Response}, routing::post, Json, Router};
+use rusqlite::Connection;
+use serde_json::{json, Value};
+use tempfile::TempDir;
+
+// What the mock server does with one /completion request
+pub enum Reply {
+    // finish normally with this text after the forced speaker prefix
+    Eos(String),
+    // run out of tokens with this partial text
+    Limit(String),
+    // finish normally, every token having this log probability
+    Scored(String, f64),
+    Status(u16, String),
+    // start a response, then drop the connection partway through the body
+    Hangup
+}
+
+#[derive(Clone, Debug)]
+pub struct Completion {
+    pub prompt: String,
+    pub body: Value,
+    // the speaker prefix the grammar forces
+    pub prefix: String,
+    // prompt tokens that weren't in the slot's cache
+    pub evaluated: usize
+}
+
+impl Completion {
+    // The Japanese line being translated
+    pub fn line(&self) -> &str {
+        let rest = match self.body["messages"].as_array() {
+            Some(messages) => messages.last().unwrap()["content"].as_str().unwrap(),
+            None => {
+                let (_, rest) = self.prompt.rsplit_once("<|start_header_id|>Japanese<|end_header_id|>\n\n").unwrap();
+                rest.split_once("<|eot_id|>").unwrap().0
+            }
+        };
+        match rest.split_once("]: ") {
+            Some((_, line)) if rest.starts_with('[') => line,
+            _ => rest
+        }
+    }
+}
+
+type Handler = dyn Fn(&Completion) -> Reply + Send + Sync;
+
+struct Inner {
+    tokenized: Mutex<Vec<String>>,
+    // the last prompt on each slot
+    slots: Mutex<HashMap<u64, String>>,
+    completions: Mutex<Vec<Completion>>,
+    handler: Box<Handler>
+}
+
+// A token the mock's tokenizer merges from two characters, as real ones merge `"[` and the like
+const MERGED: (u32, &str) = (0x110000, "\"[");
+
+// Stand-in for llama.cpp's server: /tokenize returns one token per character (the code point),
+// besides MERGED, and /completion decodes the prompt back and asks `handler` what to say.
+// /chat/completions does the same for the OpenAI backend, with each message as a `role: content`
+// line of the prompt.
+pub struct Mock {
+    pub url: String,
+    inner: Arc<Inner>
+}
+
+impl Mock {
+    pub fn start(handler: impl Fn(&Completion) -> Reply + Send + Sync + 'static) -> Self {
+        let inner = Arc::new(Inner {
+            tokenized: Mutex::default(),
+            slots: Mutex::default(),
+            completions: Mutex::default(),
+            handler: Box::new(handler)
+        });
+
+        let app = Router::new()
+            .route("/tokenize", post(tokenize))
+            .route("/completion", post(completion))
+            .route("/chat/completions", post(chat))
+            .with_state(inner.clone());
+
+        let (tx, rx) = mpsc::channel::<SocketAddr>();
+        std::thread::spawn(move || {
+            tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(async move {
+                let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+                tx.send(listener.local_addr().unwrap()).unwrap();
+                axum::serve(listener, app).await.unwrap();
+            })
+        });
+
+        Self { url: format!("http://{}", rx.recv().unwrap()), inner }
+    }
+
+    // Translates every line to `EN(<line>)` so tests can see what was sent where
+    pub fn echo() -> Self {
+        Self::start(|c| Reply::Eos(format!("EN({})", c.line())))
+    }
+
+    pub fn completions(&self) -> Vec<Completion> {
+        self.inner.completions.lock().unwrap().clone()
+    }
+
+    pub fn tokenized(&self) -> Vec<String> {
+        self.inner.tokenized.lock().unwrap().clone()
+    }
+}
+
+async fn tokenize(State(inner): State<Arc<Inner>>, Json(body): Json<Value>) -> Json<Value> {
+    let content = body["content"].as_str().unwrap().to_owned();
+    let mut pieces = Vec::new();
+    let mut rest = content.as_str();
+    while let Some(c) = rest.chars().next() {
+        let (id, piece) = if rest.starts_with(MERGED.1) { MERGED } else { (u32::from(c), &rest[..c.len_utf8()]) };
+        pieces.push((id, piece));
+        rest = &rest[piece.len()..];
+    }
+    let tokens = if body["with_pieces"] == true {
+        pieces.iter().map(|(id, piece)| json!({ "id": id, "piece": piece })).collect::<Vec<_>>()
+    } else {
+        pieces.iter().map(|(id, _)| json!(id)).collect()
+    };
+    inner.tokenized.lock().unwrap().push(content);
+    Json(json!({ "tokens": tokens }))
+}
+
+async fn completion(State(inner): State<Arc<Inner>>, Json(body): Json<Value>) -> Response {
+    let prompt = match &body["prompt"] {
+        Value::String(s) => s.clone(),
+        Value::Array(tokens) => tokens.iter().map(|t| match t.as_u64().unwrap() as u32 {
+            id if id == MERGED.0 => MERGED.1.to_owned(),
+            id => char::from_u32(id).unwrap().to_string()
+        }).collect(),
+        p => panic!("unexpected prompt {p}")
+    };
+    // GBNF literals escape like JSON strings
+    let prefix = body["grammar"].as_str()
+        .and_then(|g| g.lines().find_map(|l| l.strip_prefix("prefix ::= ")))
+        .map_or(String::new(), |p| serde_json::from_str(p).unwrap());
+
+    // like llama.cpp, reuse the common prefix but always evaluate at least one token
+    let len = prompt.chars().count();
+    let evaluated = match body["id_slot"].as_u64() {
+        Some(slot) if body["cache_prompt"] == true => {
+            let mut slots = inner.slots.lock().unwrap();
+            let cached = slots.get(&slot).map_or(0, |last| last.chars().zip(prompt.chars()).take_while(|(a, b)| a == b).count());
+            slots.insert(slot, prompt.clone());
+            len - cached.min(len.saturating_sub(1))
+        },
+        _ => len
+    };
+
+    let c = Completion { prompt, body, prefix, evaluated };
+    let reply = (inner.handler)(&c);
+    let prefix = c.prefix.clone();
+    let stream = c.body["stream"] == true;
+    let probs = c.body["n_probs"].as_u64().unwrap_or(0) > 0;
+    // a few of the settings llama.cpp reports back, with its defaults
+    let settings = json!({
+        "n_predict": c.body["n_predict"],
+        "seed": c.body.get("seed").unwrap_or(&json!(u32::MAX)),
+        "temperature": c.body.get("temperature").unwrap_or(&json!(0.8))
+    });
+    inner.completions.lock().unwrap().push(c);
+
+    let (text, stop_type, logprob) = match reply {
+        Reply::Eos(text) => (text, "eos", -1.0),
+        Reply::Limit(text) => (text, "limit", -1.0),
+        Reply::Scored(text, logprob) => (text, "eos", logprob),
+        Reply::Status(code, msg) => return (StatusCode::from_u16(code).unwrap(), msg).into_response(),
+        Reply::Hangup => return hangup()
+    };
+    let content = prefix + &text;
+    let timings = json!({ "prompt_n": evaluated });
+    let prob = |c: char| json!({ "id": u32::from(c), "token": c.to_string(), "logprob": logprob });
+
+    if stream {
+        // one event per character, then the stop
+        let mut events = content.chars()
+            .map(|c| {
+                let mut event = json!({ "content": c.to_string(), "stop": false });
+                if probs {
+                    event["completion_probabilities"] = json!([prob(c)]);
+                }
+                format!("data: {event}\n\n")
+            })
+            .collect::<String>();
+        events += &format!("data: {}\n\n", json!({ "content": "", "stop": true, "stop_type": stop_type, "generation_settings": settings, "timings": timings }));
+        ([("content-type", "text/event-stream")], events).into_response()
+    } else {
+        let mut resp = json!({ "content": content, "stop_type": stop_type, "generation_settings": settings, "timings": timings });
+        if probs {
+            resp["completion_probabilities"] = content.chars().map(prob).collect();
+        }
+        Json(resp).into_response()
+    }
+}
+
+fn hangup() -> Response {
+    // whitespace, so the headers go out, then a moment later nothing
+    let body = futures_util::stream::iter([Ok("\n".to_owned())]).chain(futures_util::stream::once(async {
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        Err(std::io::Error::other("hung up"))
+    }));
+    Body::from_stream(body).into_response()
+}
+
+async fn chat(State(inner): State<Arc<Inner>>, Json(body): Json<Value>) -> Response {
+    let prompt = body["messages"].as_array().unwrap().iter()
+        .map(|m| format!("{}: {}\n", m["role"].as_str().unwrap(), m["content"].as_str().unwrap()))
+        .collect();
+    let c = Completion { prompt, body, prefix: String::new(), evaluated: 0 };
+    let reply = (inner.handler)(&c);
+    let probs = c.body["logprobs"] == true;
+    inner.completions.lock().unwrap().push(c);
+
+    let (text, finish_reason, logprob) = match reply {
+        Reply::Eos(text) => (text, "stop", -1.0),
+        Reply::Limit(text) => (text, "length", -1.0),
+        Reply::Scored(text, logprob) => (text, "stop", logprob),
+        Reply::Status(code, msg) => return (StatusCode::from_u16(code).unwrap(), msg).into_response(),
+        Reply::Hangup => return hangup()
+    };
+    let mut choice = json!({ "message": { "role": "assistant", "content": text }, "finish_reason": finish_reason });
+    if probs {
+        choice["logprobs"] = json!({ "content": text.chars().map(|c| json!({ "token": c.to_string(), "logprob": logprob })).collect::<Vec<_>>() });
+    }
+    Json(json!({ "model": "mock", "choices": [choice] })).into_response()
+}
+
+// A working database with the tables the game script dump provides
+pub struct Fixture {
+    dir: TempDir,
+    pub db: PathBuf
+}
+
+impl Fixture {
+    pub fn new() -> Self {
+        let dir = tempfile::tempdir().unwrap();
+        let db = dir.path().join("work.db");
+        Connection::open(&db).unwrap().execute_batch("
+            CREATE TABLE dialogue (
+                scriptid INTEGER,
+                address INTEGER,
+                thread TEXT NOT NULL,
+                speaker TEXT,
+                body TEXT NOT NULL,
+                variant_body TEXT,
+                PRIMARY KEY (scriptid, address));
+            CREATE TABLE graph (
+                tScriptid INTEGER,
+                tThread TEXT,
+                hScriptid INTEGER,
+                hThread TEXT);
+        ").unwrap();
+        Self { dir, db }
+    }
+
+    pub fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn conn(&self) -> Connection {
+        Connection::open(&self.db).unwrap()
+    }
+
+    pub fn line(&self, scriptid: u16, address: u32, thread: &str, speaker: Option<&str>, body: &str) -> &Self {
+        self.variant(scriptid, address, thread, speaker, body, None)
+    }
+
+    pub fn variant(&self, scriptid: u16, address: u32, thread: &str, speaker: Option<&str>, body: &str, variant: Option<&str>) -> &Self {
+        self.conn().execute("INSERT INTO dialogue VALUES (?, ?, ?, ?, ?, ?)", (scriptid, address, thread, speaker, body, variant)).unwrap();
+        self
+    }
+
+    pub fn edge(&self, from: (u16, &str), to: (u16, &str)) -> &Self {
+        self.conn().execute("INSERT INTO graph VALUES (?, ?, ?, ?)", (from.0, from.1, to.0, to.1)).unwrap();
+        self
+    }
+
+    pub fn run(&self, args: &[&str]) -> Output {
+        let out = Command::new(env!("CARGO_BIN_EXE_graph-translate"))
+            .arg("-f").arg(&self.db)
+            .args(args)
+            .env("RUST_BACKTRACE", "0")
+            .output().unwrap();
+        eprintln!("{}", String::from_utf8_lossy(&out.stderr));
+        out
+    }
+
+    pub fn translate(&self, mock: &Mock, args: &[&str]) -> Output {
+        let endpoint = format!("server.endpoint={}", mock.url);
+        // echoed translations are full of Japanese
+        let mut all = vec!["translate", "-s", &endpoint, "-s", "runaway.japanese=false"];
+        all.extend_from_slice(args);
+        self.run(&all)
+    }
+
+    // (scriptid, address, tl_body, tl_variant_body) in address order
+    pub fn translations(&self) -> Vec<(u16, u32, String, Option<String>)> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT scriptid, address, tl_body, tl_variant_body FROM dialogueTl ORDER BY scriptid, address").unwrap();
+        stmt.query_map((), |row| row.try_into()).unwrap().collect::<Result<_, _>>().unwrap()
+    }
+}
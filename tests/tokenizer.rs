@@ -0,0 +1,158 @@
+mod common;
+
+use std::path::{Path, PathBuf};
+
+use common::{Fixture, Mock};
+use serde_json::json;
+
+// a, b and merges up to "ab" and " ab", plus one special token
+const TOKENS: &[&str] = &["a", "b", "ab", "<|eot_id|>", "Ġ", "Ġa", "Ġab"];
+const TYPES: &[i32] = &[1, 1, 1, 3, 1, 1, 1];
+const MERGES: &[&str] = &["Ġ a", "a b", "Ġa b"];
+
+fn gguf_string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u64).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn gguf_strings(out: &mut Vec<u8>, key: &str, values: &[&str]) {
+    gguf_string(out, key);
+    out.extend_from_slice(&9u32.to_le_bytes());
+    out.extend_from_slice(&8u32.to_le_bytes());
+    out.extend_from_slice(&(values.len() as u64).to_le_bytes());
+    for v in values {
+        gguf_string(out, v);
+    }
+}
+
+// Just the metadata a llama.cpp GGUF carries for its tokenizer, and one unrelated key
+fn write_gguf(dir: &Path) -> PathBuf {
+    let mut out = b"GGUF".to_vec();
+    out.extend_from_slice(&3u32.to_le_bytes());
+    out.extend_from_slice(&0u64.to_le_bytes());
+    out.extend_from_slice(&6u64.to_le_bytes());
+
+    gguf_string(&mut out, "general.alignment");
+    out.extend_from_slice(&4u32.to_le_bytes());
+    out.extend_from_slice(&32u32.to_le_bytes());
+
+    for (key, value) in [("tokenizer.ggml.model", "gpt2"), ("tokenizer.ggml.pre", "llama-bpe")] {
+        gguf_string(&mut out, key);
+        out.extend_from_slice(&8u32.to_le_bytes());
+        gguf_string(&mut out, value);
+    }
+
+    gguf_strings(&mut out, "tokenizer.ggml.tokens", TOKENS);
+    gguf_string(&mut out, "tokenizer.ggml.token_type");
+    out.extend_from_slice(&9u32.to_le_bytes());
+    out.extend_from_slice(&5u32.to_le_bytes());
+    out.extend_from_slice(&(TYPES.len() as u64).to_le_bytes());
+    for t in TYPES {
+        out.extend_from_slice(&t.to_le_bytes());
+    }
+    gguf_strings(&mut out, "tokenizer.ggml.merges", MERGES);
+
+    let path = dir.join("model.gguf");
+    std::fs::write(&path, out).unwrap();
+    path
+}
+
+// The same vocabulary as an HF tokenizer.json
+fn write_tokenizer_json(dir: &Path) -> PathBuf {
+    let vocab = TOKENS.iter().enumerate().map(|(id, t)| (t.to_string(), json!(id))).collect::<serde_json::Map<_, _>>();
+    let tokenizer = json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [{ "id": 3, "content": "<|eot_id|>", "single_word": false, "lstrip": false, "rstrip": false, "normalized": false, "special": true }],
+        "normalizer": null,
+        "pre_tokenizer": { "type": "ByteLevel", "add_prefix_space": false, "trim_offsets": true, "use_regex": true },
+        "post_processor": null,
+        "decoder": null,
+        "model": { "type": "BPE", "dropout": null, "unk_token": null, "continuing_subword_prefix": null, "end_of_word_suffix": null, "fuse_unk": false, "byte_fallback": false, "vocab": vocab, "merges": MERGES }
+    });
+
+    let path = dir.join("tokenizer.json");
+    std::fs::write(&path, tokenizer.to_string()).unwrap();
+    path
+}
+
+// One token per character with the code point as its id, like the mock server counts
+fn write_char_tokenizer(dir: &Path) -> PathBuf {
+    let ranges = [0x0a..=0x0a, 0x20..=0x7e, 0x3000..=0x30ff, 0x4e00..=0x9fff, 0xff00..=0xffef];
+    let mut vocab = ranges.into_iter().flatten()
+        .filter_map(|c: u32| Some((char::from_u32(c)?.to_string(), json!(c))))
+        .collect::<serde_json::Map<_, _>>();
+    vocab.insert("<unk>".into(), json!(0x110000));
+    let tokenizer = json!({
+        "version": "1.0",
+        "truncation": null,
+        "padding": null,
+        "added_tokens": [],
+        "normalizer": null,
+        "pre_tokenizer": { "type": "Split", "pattern": { "Regex": "(?s)." }, "behavior": "Isolated", "invert": false },
+        "post_processor": null,
+        "decoder": null,
+        "model": { "type": "WordLevel", "vocab": vocab, "unk_token": "<unk>" }
+    });
+
+    let path = dir.join("chars.json");
+    std::fs::write(&path, tokenizer.to_string()).unwrap();
+    path
+}
+
+fn tokenize(f: &Fixture, tokenizer: &Path, text: &str) -> String {
+    let tokenizer = format!("context.tokenizer={}", tokenizer.display());
+    // nothing is listening here
+    let out = f.run(&["tokenize", "--ids", "-s", &tokenizer, "-s", "server.endpoint=http://127.0.0.1:9", text]);
+    assert!(out.status.success());
+    String::from_utf8(out.stdout).unwrap()
+}
+
+#[test]
+fn gguf_vocabulary_counts_offline() {
+    let f = Fixture::new();
+    let gguf = write_gguf(f.dir());
+    assert_eq!(tokenize(&f, &gguf, "ab<|eot_id|>ab ab"), "4\n[2, 3, 2, 6]\n");
+}
+
+#[test]
+fn tokenizer_json_agrees_with_gguf() {
+    let f = Fixture::new();
+    let json = write_tokenizer_json(f.dir());
+    let gguf = write_gguf(f.dir());
+    for text in ["ab<|eot_id|>ab ab", "ba ab<|eot_id|>"] {
+        assert_eq!(tokenize(&f, &json, text), tokenize(&f, &gguf, text));
+    }
+}
+
+#[test]
+fn server_count_matches() {
+    let f = Fixture::new();
+    let chars = write_char_tokenizer(f.dir());
+    let mock = Mock::echo();
+
+    let text = "<|start_header_id|>Japanese<|end_header_id|>\n\n[少女]: 「待って」<|eot_id|>";
+    let endpoint = format!("server.endpoint={}", mock.url);
+    let out = f.run(&["tokenize", "--ids", "-s", &endpoint, text]);
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), tokenize(&f, &chars, text));
+}
+
+#[test]
+fn translate_counts_prompts_offline() {
+    let f = Fixture::new();
+    for i in 0..8u32 {
+        f.line(1, 0x10 * (i + 1), "main", Some("少女"), &format!("「{i}番目の行。」"));
+    }
+    let chars = write_char_tokenizer(f.dir());
+
+    let mock = Mock::echo();
+    let tokenizer = format!("context.tokenizer={}", chars.display());
+    assert!(f.translate(&mock, &["-s", &tokenizer, "-s", "context.n_ctx=700"]).status.success());
+
+    assert_eq!(f.translations().len(), 8);
+    assert!(mock.tokenized().is_empty());
+    for c in mock.completions() {
+        assert!(c.prompt.chars().count() <= 700 - 64, "prompt too long:\n{}", c.prompt);
+    }
+}
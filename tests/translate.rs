@@ -0,0 +1,927 @@
+mod common;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use common::{Fixture, Mock, Reply};
+use serde_json::json;
+
+fn branching() -> Fixture {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", None, "雨が降っていた。")
+        .line(1, 0x20, "main", Some("少女"), "「待って」")
+        .line(1, 0x30, "a", Some("魔法使い"), "「行こう」")
+        .line(1, 0x40, "b", None, "誰もいない。")
+        .edge((1, "main"), (1, "a"))
+        .edge((1, "main"), (1, "b"));
+    f
+}
+
+#[test]
+fn translates_every_series_once() {
+    let f = branching();
+    let mock = Mock::echo();
+
+    assert!(f.translate(&mock, &[]).status.success());
+
+    assert_eq!(f.translations(), [
+        (1, 0x10, "EN(雨が降っていた。)".into(), None),
+        (1, 0x20, "EN(「待って」)".into(), None),
+        (1, 0x30, "EN(「行こう」)".into(), None),
+        (1, 0x40, "EN(誰もいない。)".into(), None)
+    ]);
+
+    // the shared prefix is translated by the first series and only replayed by the second
+    let completions = mock.completions();
+    assert_eq!(completions.len(), 4);
+    assert_eq!(completions[1].prefix, "[Girl]: ");
+    assert_eq!(completions[2].prefix, "[Wizard]: ");
+
+    // sibling branches don't see each other
+    let last = completions.iter().find(|c| c.line() == "誰もいない。").unwrap();
+    assert!(last.prompt.contains("[Girl]: EN(「待って」)"));
+    assert!(!last.prompt.contains("行こう"));
+}
+
+#[test]
+fn existing_translations_are_context_only() {
+    let f = branching();
+    assert!(f.run(&["status"]).status.success());
+    f.conn().execute("INSERT INTO dialogueTl(scriptid, address, tl_body) VALUES (1, 16, 'It was raining.')", ()).unwrap();
+
+    let mock = Mock::echo();
+    assert!(f.translate(&mock, &[]).status.success());
+
+    let completions = mock.completions();
+    assert_eq!(completions.len(), 3);
+    assert!(completions.iter().all(|c| c.line() != "雨が降っていた。"));
+    assert!(completions[0].prompt.contains("It was raining."));
+    assert_eq!(f.translations()[0].2, "It was raining.");
+}
+
+#[test]
+fn variants_are_translated_in_a_vacuum() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", None, "雨が降っていた。")
+        .variant(1, 0x20, "main", Some("少女"), "「待って」", Some("「待ってよ」"))
+        .line(1, 0x30, "main", None, "誰もいない。");
+
+    let mock = Mock::echo();
+    assert!(f.translate(&mock, &[]).status.success());
+
+    assert_eq!(f.translations()[1], (1, 0x20, "EN(「待って」)".into(), Some("EN(「待ってよ」)".into())));
+
+    let completions = mock.completions();
+    assert_eq!(completions.iter().map(|c| c.line()).collect::<Vec<_>>(),
+        ["雨が降っていた。", "「待ってよ」", "「待って」", "誰もいない。"]);
+    // neither the main line nor anything after it sees the variant
+    assert!(!completions[2].prompt.contains("待ってよ"));
+    assert!(!completions[3].prompt.contains("待ってよ"));
+    assert!(completions[3].prompt.contains("EN(「待って」)"));
+}
+
+#[test]
+fn history_is_trimmed_to_fit_the_context() {
+    const N_CTX: usize = 600;
+    const N_PREDICT: usize = 64;
+
+    let f = Fixture::new();
+    for i in 0..8u32 {
+        f.line(1, 0x10 * (i + 1), "main", None, &format!("{i}番目の行。"));
+    }
+
+    let mock = Mock::echo();
+    let n_ctx = format!("context.n_ctx={N_CTX}");
+    let n_predict = format!("context.n_predict={N_PREDICT}");
+    assert!(f.translate(&mock, &["-s", &n_ctx, "-s", &n_predict]).status.success());
+
+    assert_eq!(f.translations().len(), 8);
+
+    let completions = mock.completions();
+    for c in &completions {
+        // one token per character
+        assert!(c.prompt.chars().count() <= N_CTX - N_PREDICT, "prompt too long:\n{}", c.prompt);
+        assert_eq!(c.body["n_predict"], N_PREDICT);
+    }
+
+    let last = completions.last().unwrap();
+    assert_eq!(last.line(), "7番目の行。");
+    assert!(last.prompt.contains("EN(6番目の行。)"));
+    assert!(!last.prompt.contains("0番目の行。"));
+
+    // at most one tokenize request per line, and every history entry is tokenized once
+    let tokenized = mock.tokenized();
+    assert!(tokenized.len() <= completions.len());
+    assert_eq!(tokenized.iter().filter(|t| t.contains("EN(3番目の行。)")).count(), 1);
+}
+
+#[test]
+fn header_too_long_fails_the_series_cleanly() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", Some("少女"), "「待って」")
+        .line(2, 0x10, "main", None, "誰もいない。");
+
+    let mock = Mock::echo();
+    let out = f.translate(&mock, &["-s", "context.n_ctx=260", "-s", "recovery.n_predict=[]"]);
+    assert!(out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("without any history"));
+    assert!(!stderr.contains("panicked"));
+
+    // the short line still fits
+    assert_eq!(f.translations(), [(2, 0x10, "EN(誰もいない。)".into(), None)]);
+}
+
+#[test]
+fn max_tokens_stops_the_series_only() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", None, "雨が降っていた。")
+        .line(1, 0x20, "main", None, "とても長い行。")
+        .line(1, 0x30, "main", None, "誰もいない。")
+        .line(2, 0x10, "main", None, "別の話。");
+
+    let mock = Mock::start(|c| match c.line() {
+        "とても長い行。" => Reply::Limit("and so on and so on".into()),
+        line => Reply::Eos(format!("EN({line})"))
+    });
+    let out = f.translate(&mock, &[]);
+    assert!(out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("maximum number of tokens reached: and so on"));
+
+    // everything up to the failure is kept, and the next series still runs
+    assert_eq!(f.translations(), [
+        (1, 0x10, "EN(雨が降っていた。)".into(), None),
+        (2, 0x10, "EN(別の話。)".into(), None)
+    ]);
+}
+
+#[test]
+fn placeholders_are_restored() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", Some("少女"), "「#Name[1]、待って」")
+        .line(1, 0x20, "main", None, "誰もいない。");
+
+    let mock = Mock::start(|c| match c.line() {
+        "「玻ヰ璃、待って」" => Reply::Eos("\"Hairi, wait!\"".into()),
+        line => Reply::Eos(format!("EN({line})"))
+    });
+    assert!(f.translate(&mock, &[]).status.success());
+
+    assert_eq!(f.translations()[0].2, "\"#Name[1], wait!\"");
+    // the model keeps seeing the display name
+    assert!(mock.completions()[1].prompt.contains("[Girl]: \"Hairi, wait!\""));
+}
+
+#[test]
+fn busy_server_is_retried() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", None, "雨が降っていた。");
+
+    let calls = AtomicUsize::new(0);
+    let mock = Mock::start(move |c| match calls.fetch_add(1, Ordering::SeqCst) {
+        0 => Reply::Status(503, "slot unavailable".into()),
+        1 => Reply::Status(429, "too many requests".into()),
+        _ => Reply::Eos(format!("EN({})", c.line()))
+    });
+    let out = f.translate(&mock, &["-s", "retry.initial_delay_ms=1"]);
+    assert!(out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("1:10 completion"));
+
+    assert_eq!(mock.completions().len(), 3);
+    assert_eq!(f.translations(), [(1, 0x10, "EN(雨が降っていた。)".into(), None)]);
+}
+
+#[test]
+fn dropped_connections_are_retried() {
+    for stream in [false, true] {
+        let f = Fixture::new();
+        f.line(1, 0x10, "main", None, "雨が降っていた。");
+
+        let calls = AtomicUsize::new(0);
+        let mock = Mock::start(move |c| match calls.fetch_add(1, Ordering::SeqCst) {
+            0 => Reply::Hangup,
+            _ => Reply::Eos(format!("EN({})", c.line()))
+        });
+        let out = f.translate(&mock, &["-s", "retry.initial_delay_ms=1", "-s", &format!("server.stream={stream}")]);
+        assert!(out.status.success());
+        assert_eq!(mock.completions().len(), 2);
+        assert_eq!(f.translations(), [(1, 0x10, "EN(雨が降っていた。)".into(), None)]);
+    }
+
+    // a server that accepts connections and closes them straight away
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("server.endpoint=http://{}", listener.local_addr().unwrap());
+    std::thread::spawn(move || {
+        for conn in listener.incoming() {
+            drop(conn);
+        }
+    });
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", None, "雨が降っていた。");
+    let out = f.run(&["translate", "-s", &endpoint, "-s", "retry.initial_delay_ms=1", "-s", "retry.max_retries=2"]);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("retry 2/2"));
+    assert!(stderr.contains("SERIES FAILED"));
+}
+
+#[test]
+fn client_errors_are_not_retried() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", None, "雨が降っていた。");
+
+    let mock = Mock::start(|_| Reply::Status(400, "bad grammar".into()));
+    let out = f.translate(&mock, &["-s", "retry.initial_delay_ms=1"]);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("bad grammar"));
+
+    assert_eq!(mock.completions().len(), 1);
+    assert!(f.translations().is_empty());
+}
+
+#[test]
+fn retries_give_up_eventually() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", None, "雨が降っていた。");
+
+    let mock = Mock::start(|_| Reply::Status(500, "oops".into()));
+    let out = f.translate(&mock, &["-s", "retry.initial_delay_ms=1", "-s", "retry.max_retries=2"]);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("SERIES FAILED"));
+
+    assert_eq!(mock.completions().len(), 3);
+    assert!(f.translations().is_empty());
+}
+
+fn strategy(f: &Fixture, address: u32) -> (String, Option<u32>, Option<u32>) {
+    f.conn().query_row("SELECT strategy, n_predict, pieces FROM dialogueTlMeta WHERE scriptid = 1 AND address = ?", [address], |row| row.try_into()).unwrap()
+}
+
+#[test]
+fn long_lines_get_a_larger_budget() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", None, "雨が降っていた。")
+        .line(1, 0x20, "main", None, "とても長い行。");
+
+    let mock = Mock::start(|c| match (c.line(), c.body["n_predict"].as_u64()) {
+        ("とても長い行。", Some(64 | 128)) => Reply::Limit("and so on".into()),
+        (line, _) => Reply::Eos(format!("EN({line})"))
+    });
+    assert!(f.translate(&mock, &[]).status.success());
+
+    assert_eq!(f.translations()[1].2, "EN(とても長い行。)");
+    assert_eq!(strategy(&f, 0x10), ("direct".into(), None, None));
+    assert_eq!(strategy(&f, 0x20), ("budget".into(), Some(256), None));
+}
+
+#[test]
+fn long_lines_are_split_as_a_last_resort() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", Some("少女"), "「雨だ。寒いね！　帰ろう？」");
+
+    let mock = Mock::start(|c| match c.line() {
+        "「雨だ。寒いね！　帰ろう？」" => Reply::Limit("and so on".into()),
+        line => Reply::Eos(format!("EN({line})"))
+    });
+    assert!(f.translate(&mock, &[]).status.success());
+
+    assert_eq!(f.translations()[0].2, "EN(「雨だ。) EN(寒いね！) EN(　帰ろう？」)");
+    assert_eq!(strategy(&f, 0x10), ("split".into(), None, Some(3)));
+
+    // later pieces see the earlier ones, with the speaker
+    let completions = mock.completions();
+    let last = completions.last().unwrap();
+    assert_eq!(last.prefix, "[Girl]: ");
+    assert!(last.prompt.contains("[Girl]: EN(寒いね！)"));
+    assert_eq!(last.body["n_predict"], 256);
+}
+
+#[test]
+fn streamed_completions_are_stored_the_same() {
+    let f = branching();
+    let mock = Mock::echo();
+    let out = f.translate(&mock, &["-s", "server.stream=true"]);
+    assert!(out.status.success());
+
+    assert!(mock.completions().iter().all(|c| c.body["stream"] == true));
+    assert_eq!(f.translations()[1].2, "EN(「待って」)");
+    // echoed live as well as at the end
+    assert!(String::from_utf8_lossy(&out.stderr).contains("[Girl]: EN(「待って」)\n"));
+}
+
+#[test]
+fn repetition_loops_are_cut_short() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", None, "雨だ。寒いね。")
+        .line(1, 0x20, "main", None, "誰もいない。");
+
+    let mock = Mock::start(|c| match c.line() {
+        "雨だ。寒いね。" => Reply::Eos(format!("It's raining.{}", " Ha ha".repeat(40))),
+        line => Reply::Eos(format!("EN({line})"))
+    });
+    let out = f.translate(&mock, &["-s", "server.stream=true", "-s", "recovery.n_predict=[]"]);
+    assert!(out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("\" Ha ha\" repeated 8 times"));
+    // stopped as soon as the loop was spotted
+    assert!(!stderr.contains(&" Ha ha".repeat(10)));
+
+    // recovered by splitting, like running out of tokens
+    assert_eq!(f.translations()[0].2, "EN(雨だ。) EN(寒いね。)");
+    assert_eq!(strategy(&f, 0x10), ("split".into(), None, Some(2)));
+}
+
+#[test]
+fn japanese_output_is_rejected() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", None, "雨が降っていた。");
+
+    let mock = Mock::echo();
+    let out = f.translate(&mock, &["-s", "runaway.japanese=true"]);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("Japanese in the output ('雨')"));
+    assert!(f.translations().is_empty());
+}
+
+#[test]
+fn sampling_parameters_are_sent_and_recorded() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", None, "雨が降っていた。");
+
+    let mock = Mock::echo();
+    assert!(f.translate(&mock, &["-s", "sampling.seed=42", "-s", "sampling.top_k=20"]).status.success());
+
+    let body = &mock.completions()[0].body;
+    assert_eq!((&body["seed"], &body["top_k"]), (&json!(42), &json!(20)));
+    assert!(body.get("temperature").is_none());
+
+    let params: String = f.conn().query_row("SELECT params FROM dialogueTlMeta", (), |row| row.get(0)).unwrap();
+    let params: serde_json::Value = serde_json::from_str(&params).unwrap();
+    assert_eq!(params["backend"], "llama.cpp");
+    assert_eq!(params["request"]["seed"], 42);
+    assert_eq!(params["request"]["n_predict"], 64);
+    assert!(params["request"].get("prompt").is_none());
+    // including what the server filled in
+    assert_eq!(params["server"]["temperature"], 0.8);
+}
+
+#[test]
+fn best_candidate_is_picked_and_the_rest_kept() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", None, "雨が降っていた。")
+        .line(1, 0x20, "main", None, "誰もいない。");
+
+    let mock = Mock::start(|c| match (c.line(), c.body["seed"].as_u64()) {
+        ("雨が降っていた。", Some(10)) => Reply::Scored("It rained rain.".into(), -2.0),
+        ("雨が降っていた。", Some(11)) => Reply::Scored("It was raining.".into(), -0.1),
+        ("雨が降っていた。", _) => Reply::Scored("Rain was falling.".into(), -0.5),
+        (line, _) => Reply::Scored(format!("EN({line})"), -1.0)
+    });
+    let out = f.translate(&mock, &["-s", "candidates.n=3", "-s", "sampling.seed=10"]);
+    assert!(out.status.success());
+
+    assert!(mock.completions().iter().all(|c| c.body["n_probs"] == 1));
+    assert_eq!(f.translations()[0].2, "It was raining.");
+    // the next line follows on from the pick
+    assert!(mock.completions()[3].prompt.contains("It was raining."));
+
+    let list = f.run(&["candidates", "list", "1:10"]);
+    assert_eq!(String::from_utf8(list.stdout).unwrap(), "\
+        \x200\t-2.000\tdirect\tIt rained rain.\n\
+        *1\t-0.100\tdirect\tIt was raining.\n\
+        \x202\t-0.500\tdirect\tRain was falling.\n");
+
+    assert!(f.run(&["candidates", "pick", "1:10", "2"]).status.success());
+    assert_eq!(f.translations()[0].2, "Rain was falling.");
+    let (seed, logprob): (u32, f64) = f.conn().query_row("SELECT params ->> '$.request.seed', logprob FROM dialogueTlMeta WHERE address = 16", (), |row| row.try_into()).unwrap();
+    assert_eq!(seed, 12);
+    assert_eq!(logprob, -0.5);
+
+    assert!(!f.run(&["candidates", "pick", "1:10", "3"]).status.success());
+
+    // translated again without candidates, there are none left to pick
+    f.conn().execute("DELETE FROM dialogueTl", ()).unwrap();
+    assert!(f.translate(&mock, &[]).status.success());
+    assert!(!f.run(&["candidates", "list", "1:10"]).status.success());
+}
+
+#[test]
+fn failed_candidates_are_left_out() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", None, "雨が降っていた。");
+
+    let mock = Mock::start(|c| match c.body["seed"].as_u64() {
+        Some(10) => Reply::Status(400, "bad sample".into()),
+        Some(11) => Reply::Scored("It was raining.".into(), -0.1),
+        _ => Reply::Scored("Rain was falling.".into(), -0.5)
+    });
+    let out = f.translate(&mock, &["-s", "candidates.n=3", "-s", "sampling.seed=10"]);
+    assert!(out.status.success());
+    assert!(String::from_utf8_lossy(&out.stderr).contains("candidate 0 failed"));
+    assert_eq!(f.translations()[0].2, "It was raining.");
+    assert_eq!(f.run(&["candidates", "list", "1:10"]).stdout.iter().filter(|&&b| b == b'\n').count(), 2);
+
+    // but with none to pick from, the line fails
+    f.conn().execute("DELETE FROM dialogueTl", ()).unwrap();
+    let mock = Mock::start(|_| Reply::Status(400, "bad sample".into()));
+    let out = f.translate(&mock, &["-s", "candidates.n=3", "-s", "sampling.seed=10"]);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("SERIES FAILED"));
+    assert!(f.translations().is_empty());
+}
+
+#[test]
+fn glossary_scorer_prefers_the_glossary_rendering() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", None, "魔導炉が止まった。");
+    assert!(f.run(&["glossary", "add", "魔導炉", "--target", "Magic Furnace", "--type", "Machine"]).status.success());
+
+    let mock = Mock::start(|c| match c.body["seed"].as_u64() {
+        Some(1) => Reply::Eos("The mana reactor stopped.".into()),
+        _ => Reply::Eos("The magic furnace stopped.".into())
+    });
+    let out = f.translate(&mock, &["-s", "candidates.n=2", "-s", "candidates.scorer=glossary", "-s", "sampling.seed=1"]);
+    assert!(out.status.success());
+    assert_eq!(f.translations()[0].2, "The magic furnace stopped.");
+}
+
+#[test]
+fn prompts_follow_the_chosen_template() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", None, "雨が降っていた。")
+        .line(1, 0x20, "main", Some("少女"), "「待って」");
+
+    let mock = Mock::start(|_| Reply::Eos("EN".into()));
+    assert!(f.translate(&mock, &["-s", "prompt.template=chatml"]).status.success());
+
+    let c = &mock.completions()[1];
+    assert!(c.prompt.starts_with("<|im_start|>system\n"));
+    assert!(c.prompt.contains("<|im_start|>user\n雨が降っていた。<|im_end|>\n<|im_start|>assistant\nEN<|im_end|>\n"));
+    assert!(c.prompt.ends_with("<|im_start|>user\n[少女]: 「待って」<|im_end|>\n<|im_start|>assistant\n"));
+    assert_eq!(c.prefix, "[Girl]: ");
+}
+
+#[test]
+fn gemma_turns_alternate() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", None, "雨が降っていた。")
+        .line(1, 0x20, "main", None, "雨が降っていた。");
+    assert!(f.run(&["status"]).status.success());
+    let input = f.dir().join("reviewed.jsonl");
+    std::fs::write(&input, "{\"scriptid\": 1, \"address\": 16, \"tl_body\": \"It was raining.\"}\n").unwrap();
+    assert!(f.run(&["import", "--reviewed", input.to_str().unwrap()]).status.success());
+
+    let mock = Mock::start(|_| Reply::Eos("EN".into()));
+    assert!(f.translate(&mock, &["-s", "prompt.template=gemma", "-s", "memory.mode=hint"]).status.success());
+
+    // the hint goes in with the line rather than in a turn of its own
+    let prompt = &mock.completions()[0].prompt;
+    assert!(prompt.ends_with("<start_of_turn>user\n(This line has been translated before as: It was raining.)\n雨が降っていた。<end_of_turn>\n<start_of_turn>model\n"));
+    let roles = prompt.split("<start_of_turn>").skip(1).map(|t| t.split_once('\n').unwrap().0).collect::<Vec<_>>();
+    assert_eq!(roles, ["user", "model", "user", "model", "user", "model"]);
+}
+
+#[test]
+fn templates_can_be_read_from_a_file() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", Some("少女"), "「待って」");
+    std::fs::write(f.dir().join("plain.toml"), r##"
+        header = "<s>{metadata}"
+        metadata_line = "# {line}\n"
+        speaker = "{name}: "
+        source = "<s>JA {speaker}{line}\n"
+        target = "<s>EN {speaker}{line}\n"
+        prompt = "EN "
+    "##).unwrap();
+    let config = f.dir().join("project.toml");
+    std::fs::write(&config, "[prompt]\ntemplate = \"plain.toml\"\n").unwrap();
+
+    let mock = Mock::start(|_| Reply::Eos("Wait!".into()));
+    assert!(f.translate(&mock, &["-c", config.to_str().unwrap()]).status.success());
+
+    let c = &mock.completions()[0];
+    assert!(c.prompt.ends_with("<s>JA 少女: 「待って」\nEN "));
+    assert_eq!(c.prefix, "Girl: ");
+    assert_eq!(f.translations()[0].2, "Wait!");
+}
+
+#[test]
+fn grammar_follows_the_line() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", Some("少女"), "「#Name[2]、待って」")
+        .line(1, 0x20, "main", None, "（#Name[1]と#Name[2]……）");
+
+    let mock = Mock::start(|c| Reply::Eos(match c.line() {
+        "「ハイリ、待って」" => "\"Hairi, wait!\"".into(),
+        _ => "(Hairi and Hairi...)".into()
+    }));
+    assert!(f.translate(&mock, &[]).status.success());
+
+    let completions = mock.completions();
+    assert_eq!(completions[0].body["grammar"], concat!(
+        "root ::= prefix \"\\\"\" body \"\\\"\"\n",
+        "prefix ::= \"[Girl]: \"\n",
+        "body ::= text \"Hairi\" text\n",
+        "text ::= [^\\x00\\u3040-\\u30FF\\u3400-\\u4DBF\\u4E00-\\u9FFF\\uFF66-\\uFF9F]*\n"
+    ));
+    let grammar = completions[1].body["grammar"].as_str().unwrap();
+    assert!(grammar.starts_with("root ::= \"(\" body \")\"\nbody ::= text \"Hairi\" text \"Hairi\" text\n"));
+    assert_eq!(f.translations()[0].2, "\"#Name[2], wait!\"");
+
+    let mock = Mock::start(|_| Reply::Eos("Wait".into()));
+    f.conn().execute("DELETE FROM dialogueTl", ()).unwrap();
+    f.translate(&mock, &["-s", "grammar.brackets=false", "-s", "grammar.japanese=false", "-s", "grammar.placeholders=false"]);
+    assert_eq!(mock.completions()[0].body["grammar"], "root ::= prefix body\nprefix ::= \"[Girl]: \"\nbody ::= text\ntext ::= [^\\x00]*\n");
+}
+
+#[test]
+fn speaker_names_are_escaped_in_the_grammar() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", Some("謎の声"), "誰？");
+    assert!(f.run(&["characters", "add", "謎の声", "--en", "\"X\" \\ Y"]).status.success());
+
+    let mock = Mock::start(|_| Reply::Eos("Who?".into()));
+    assert!(f.translate(&mock, &[]).status.success());
+
+    let c = &mock.completions()[0];
+    assert!(c.body["grammar"].as_str().unwrap().contains("prefix ::= \"[\\\"X\\\" \\\\ Y]: \"\n"));
+    assert_eq!(c.prefix, "[\"X\" \\ Y]: ");
+    assert_eq!(f.translations()[0].2, "Who?");
+}
+
+#[test]
+fn prompts_keep_a_cacheable_prefix() {
+    let f = Fixture::new();
+    for i in 0..16u32 {
+        f.line(1, 0x10 * (i + 1), "main", Some("少女"), &format!("「{i}番目の行」"));
+    }
+
+    // lines that had to be evaluated mostly from scratch
+    let misses = |mock: &Mock| mock.completions().iter().skip(1)
+        .filter(|c| c.evaluated > c.prompt.chars().count() / 2)
+        .count();
+
+    let mock = Mock::echo();
+    let out = f.translate(&mock, &["-s", "context.n_ctx=1200"]);
+    assert!(out.status.success());
+    assert!(mock.completions().iter().all(|c| c.body["cache_prompt"] == true && c.body["id_slot"] == 0));
+    assert!(mock.completions().iter().all(|c| c.prompt.chars().count() <= 1200 - 64));
+    // history is dropped in blocks, so most lines only add to the last prompt
+    assert!(misses(&mock) <= 3, "{} misses", misses(&mock));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("from cache"));
+
+    // trimming just enough changes the prefix every line
+    f.conn().execute("DELETE FROM dialogueTl", ()).unwrap();
+    let mock = Mock::echo();
+    assert!(f.translate(&mock, &["-s", "context.n_ctx=1200", "-s", "context.keep=1.0"]).status.success());
+    assert!(misses(&mock) > 6);
+}
+
+#[test]
+fn independent_series_run_side_by_side() {
+    let f = branching();
+    for i in 0..3u32 {
+        f.line(2, 0x10 * (i + 1), "main", None, &format!("別の話{i}。"));
+    }
+
+    let mock = Mock::echo();
+    assert!(f.translate(&mock, &["-s", "server.slots=2"]).status.success());
+    assert_eq!(f.translations().len(), 7);
+
+    let completions = mock.completions();
+    assert_eq!(completions.len(), 7);
+    let at = |line: &str| completions.iter().position(|c| c.line() == line).unwrap();
+    // script 2 shares nothing with script 1, so it doesn't wait for it...
+    assert!(at("別の話0。") < at("誰もいない。"));
+    assert!(completions.iter().any(|c| c.body["id_slot"] == 1));
+    // ...but the branches of script 1 share their start, which is translated once and seen by both
+    let last = &completions[at("誰もいない。")];
+    assert!(at("「待って」") < at("誰もいない。"));
+    assert!(last.prompt.contains("[Girl]: EN(「待って」)"));
+    assert!(!last.prompt.contains("行こう"));
+}
+
+#[test]
+fn plan_lists_the_runs_translate_makes() {
+    let f = branching();
+    f.line(1, 0x50, "a1", None, "雨が止んだ。")
+        .line(1, 0x60, "a2", None, "風が吹いた。")
+        .line(2, 0x10, "main", None, "別の話。")
+        .line(3, 0x10, "main", None, "終わり。")
+        .edge((1, "a"), (1, "a1"))
+        .edge((1, "a"), (1, "a2"));
+    assert!(f.run(&["status"]).status.success());
+    f.conn().execute("
+        INSERT INTO dialogueTl(scriptid, address, tl_body)
+        VALUES (1, 48, 'Let''s go.'), (1, 96, 'The wind blew.'), (3, 16, 'The end.')", ()).unwrap();
+
+    let plan = f.run(&["plan"]);
+    assert!(plan.status.success());
+    assert_eq!(String::from_utf8(plan.stdout).unwrap(), "    \
+            1       2  1:main (2)\n    \
+            2       1  (after 1) 1:a -> 1:a1 (1)\n    \
+            3    done  (after 2) 1:a2\n    \
+            4       1  (after 1) 1:b (1)\n    \
+            5       1  2:main (1)\n    \
+            6    done  3:main\n\
+        \n\
+        runs:   4 to run, 2 done\n\
+        lines:  5 untranslated\n");
+
+    let plan = f.run(&["plan", "--format", "json"]);
+    let plan = serde_json::from_slice::<serde_json::Value>(&plan.stdout).unwrap();
+    assert_eq!(plan["totals"]["done"], 2);
+    let planned = plan["runs"].as_array().unwrap().iter()
+        .filter(|r| r["done"] == false)
+        .flat_map(|r| r["nodes"].as_array().unwrap())
+        .map(|n| format!("--------- {}:{} ---------", n["scriptid"], n["thread"].as_str().unwrap()))
+        .collect::<Vec<_>>();
+
+    let mock = Mock::echo();
+    let out = f.translate(&mock, &[]);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    let walked = stderr.lines().filter(|l| l.starts_with("---------")).collect::<Vec<_>>();
+    assert_eq!(walked, planned);
+}
+
+#[test]
+fn failed_runs_are_tried_once_per_leaf() {
+    let f = branching();
+
+    // the shared start fails once, and is tried again for the other branch
+    let calls = AtomicUsize::new(0);
+    let mock = Mock::start(move |c| match (c.line(), calls.fetch_add(1, Ordering::SeqCst)) {
+        ("「待って」", 1) => Reply::Status(400, "bad grammar".into()),
+        (line, _) => Reply::Eos(format!("EN({line})"))
+    });
+    let out = f.translate(&mock, &[]);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("trying 1:main again (1 left)"));
+    assert_eq!(f.translations().len(), 4);
+
+    // and given up on after that, saying what was left
+    f.conn().execute("DELETE FROM dialogueTl", ()).unwrap();
+    let mock = Mock::start(|c| match c.line() {
+        "「待って」" => Reply::Status(400, "bad grammar".into()),
+        line => Reply::Eos(format!("EN({line})"))
+    });
+    let out = f.translate(&mock, &[]);
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(stderr.matches("SERIES FAILED").count(), 2);
+    assert!(stderr.contains("GAVE UP on 1:main and below: 4 lines were untranslated"));
+    assert_eq!(f.translations().len(), 1);
+}
+
+#[test]
+fn shared_nodes_are_read_once() {
+    let f = branching();
+    f.line(1, 0x50, "a1", None, "雨が止んだ。")
+        .line(1, 0x60, "a2", None, "風が吹いた。")
+        .edge((1, "a"), (1, "a1"))
+        .edge((1, "a"), (1, "a2"));
+
+    let mock = Mock::echo();
+    let out = f.translate(&mock, &[]);
+    assert!(out.status.success());
+    assert_eq!(f.translations().len(), 6);
+
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert_eq!(stderr.matches("--------- 1:main ---------").count(), 1);
+    assert_eq!(stderr.matches("--------- 1:a ---------").count(), 1);
+
+    // each branch picks up the context from where it split off
+    let completions = mock.completions();
+    let prompt = |line: &str| &completions.iter().find(|c| c.line() == line).unwrap().prompt;
+    assert!(prompt("風が吹いた。").contains("EN(「行こう」)"));
+    assert!(!prompt("風が吹いた。").contains("雨が止んだ"));
+    assert!(prompt("誰もいない。").contains("EN(「待って」)"));
+    assert!(!prompt("誰もいない。").contains("行こう"));
+}
+
+#[test]
+fn repeated_lines_are_hinted_or_reused() {
+    let f = Fixture::new();
+    f.line(1, 0x08, "main", None, "誰もいない。")
+        .line(1, 0x10, "main", Some("少女"), "「待って」")
+        .line(1, 0x20, "main", None, "誰もいない。")
+        .line(1, 0x30, "main", Some("少女"), "「待って」")
+        .line(1, 0x40, "main", None, "「待って」");
+    assert!(f.run(&["status"]).status.success());
+    // imported, so taken as reviewed
+    let input = f.dir().join("reviewed.jsonl");
+    std::fs::write(&input, "{\"scriptid\": 1, \"address\": 16, \"tl_body\": \"Hold on!\"}\n").unwrap();
+    assert!(f.run(&["import", "--reviewed", input.to_str().unwrap()]).status.success());
+    // model output from before anything was marked, which isn't
+    f.conn().execute("INSERT INTO dialogueTl(scriptid, address, tl_body) VALUES (1, 8, 'Nobody.')", ()).unwrap();
+    let memory = |f: &Fixture| -> Vec<(u32, u32, bool)> {
+        let conn = f.conn();
+        let mut stmt = conn.prepare("SELECT address, source_address, reused FROM dialogueTlMemory ORDER BY address").unwrap();
+        stmt.query_map((), |row| row.try_into()).unwrap().collect::<Result<_, _>>().unwrap()
+    };
+
+    let mock = Mock::echo();
+    assert!(f.translate(&mock, &["-s", "memory.mode=hint"]).status.success());
+    let completions = mock.completions();
+    assert_eq!(completions.len(), 3);
+    assert!(!completions[0].prompt.contains("translated before"));
+    assert!(completions[1].prompt.contains("translated before as: Hold on!<|eot_id|>"));
+    // a different speaker is a different line
+    assert!(!completions[2].prompt.contains("translated before"));
+    assert_eq!(memory(&f), [(0x30, 0x10, false)]);
+
+    f.conn().execute("DELETE FROM dialogueTl WHERE address > 16", ()).unwrap();
+    let mock = Mock::echo();
+    assert!(f.translate(&mock, &["-s", "memory.mode=reuse"]).status.success());
+    assert_eq!(mock.completions().len(), 2);
+    assert_eq!(f.translations()[3].2, "Hold on!");
+    assert_eq!(memory(&f), [(0x30, 0x10, true)]);
+}
+
+#[test]
+fn confident_translations_are_remembered() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", None, "雨が降っていた。")
+        .line(1, 0x20, "main", None, "誰もいない。")
+        .line(1, 0x30, "main", None, "雨が降っていた。")
+        .line(1, 0x40, "main", None, "誰もいない。");
+
+    let mock = Mock::start(|c| match c.line() {
+        "雨が降っていた。" => Reply::Scored("It was raining.".into(), -0.1),
+        _ => Reply::Scored("Nobody was there.".into(), -2.0)
+    });
+    let out = f.translate(&mock, &["-s", "memory.mode=reuse", "-s", "memory.min_logprob=-0.5"]);
+    assert!(out.status.success());
+
+    let completions = mock.completions();
+    assert!(completions.iter().all(|c| c.body["n_probs"] == 1));
+    // the unsure one is asked again
+    assert_eq!(completions.iter().map(|c| c.line()).collect::<Vec<_>>(), ["雨が降っていた。", "誰もいない。", "誰もいない。"]);
+    assert_eq!(f.translations()[2].2, "It was raining.");
+}
+
+#[test]
+fn similar_lines_are_shown_as_examples() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", None, "雨が降っていた。")
+        .line(2, 0x10, "main", None, "雨が降っている。")
+        .line(2, 0x20, "main", None, "誰もいない。");
+    assert!(f.run(&["status"]).status.success());
+    f.conn().execute("INSERT INTO dialogueTl(scriptid, address, tl_body) VALUES (2, 16, 'It is raining.'), (2, 32, 'Nobody is here.')", ()).unwrap();
+
+    let mock = Mock::echo();
+    assert!(f.translate(&mock, &[]).status.success());
+    assert!(!mock.completions()[0].prompt.contains("[example]"));
+
+    f.conn().execute("DELETE FROM dialogueTl WHERE scriptid = 1", ()).unwrap();
+    let mock = Mock::echo();
+    assert!(f.translate(&mock, &["-s", "examples.n=2"]).status.success());
+    let prompt = &mock.completions()[0].prompt;
+    assert!(prompt.contains("[example] 雨が降っている。 => It is raining."));
+    // nothing alike
+    assert!(!prompt.contains("Nobody is here."));
+
+    f.conn().execute("DELETE FROM dialogueTl WHERE scriptid = 1", ()).unwrap();
+    let mock = Mock::echo();
+    assert!(f.translate(&mock, &["-s", "examples.n=2", "-s", "examples.max_tokens=1"]).status.success());
+    assert!(!mock.completions()[0].prompt.contains("[example]"));
+}
+
+#[test]
+fn examples_are_counted_one_at_a_time() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", None, "「雨が降っていた」")
+        .line(2, 0x10, "main", None, "「雨が降っている」")
+        .line(2, 0x20, "main", None, "「雨が降ってきた」");
+    assert!(f.run(&["status"]).status.success());
+    f.conn().execute("INSERT INTO dialogueTl(scriptid, address, tl_body) VALUES (2, 16, '\"It''s raining.\"'), (2, 32, '\"Here comes the rain.\"')", ()).unwrap();
+
+    // together, the end of one example and the start of the next would make one token
+    let mock = Mock::echo();
+    assert!(f.translate(&mock, &["-s", "examples.n=2"]).status.success());
+    let prompt = &mock.completions()[0].prompt;
+    assert!(prompt.contains("\"It's raining.\""));
+    assert!(prompt.contains("\"Here comes the rain.\""));
+}
+
+#[test]
+fn openai_backend_speaks_chat_completions() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", Some("少女"), "「待って」")
+        .line(1, 0x20, "main", None, "とても長い行。")
+        .line(1, 0x30, "main", Some("少女"), "「待って」");
+
+    let mock = Mock::start(|c| match (c.line(), c.body["max_tokens"].as_u64()) {
+        // echoing the speaker, as the history does
+        ("「待って」", _) => Reply::Scored("[Girl]: \"Wait!\"".into(), -0.1),
+        ("とても長い行。", Some(64 | 128)) => Reply::Limit("and so on".into()),
+        (line, _) => Reply::Eos(format!("EN({line})"))
+    });
+    let endpoint = format!("openai.endpoint={}", mock.url);
+    let out = f.translate(&mock, &[
+        "-s", "backend=openai", "-s", &endpoint,
+        "-s", "memory.mode=hint", "-s", "memory.min_logprob=-0.5"
+    ]);
+    assert!(out.status.success());
+
+    assert_eq!(f.translations(), [
+        (1, 0x10, "\"Wait!\"".into(), None),
+        (1, 0x20, "EN(とても長い行。)".into(), None),
+        (1, 0x30, "\"Wait!\"".into(), None)
+    ]);
+    // running out of tokens is recovered from as with llama.cpp
+    assert_eq!(strategy(&f, 0x20), ("budget".into(), Some(256), None));
+
+    let completions = mock.completions();
+    let last = completions.last().unwrap();
+    let roles = last.body["messages"].as_array().unwrap().iter().map(|m| m["role"].as_str().unwrap()).collect::<Vec<_>>();
+    assert_eq!(roles, ["system", "user", "assistant", "user", "assistant", "user"]);
+    assert!(last.body["messages"][0]["content"].as_str().unwrap().ends_with("translated before as: \"Wait!\""));
+    assert!(last.prompt.contains("assistant: [Girl]: \"Wait!\"\n"));
+}
+
+#[test]
+fn strict_preflight_refuses_unresolved_speakers() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", Some("謎の声"), "「誰だ」");
+
+    let mock = Mock::echo();
+    let out = f.translate(&mock, &["-s", "preflight.strict=true"]);
+    assert!(!out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("謎の声 (1 lines)"));
+    assert!(stderr.contains("refusing to translate"));
+    assert!(mock.completions().is_empty());
+
+    // otherwise it's only reported, and the series trips over it later
+    let out = f.translate(&mock, &[]);
+    assert!(out.status.success());
+    let stderr = String::from_utf8_lossy(&out.stderr);
+    assert!(stderr.contains("謎の声 (1 lines)"));
+    assert!(stderr.contains("SERIES FAILED"));
+}
+
+#[test]
+fn normalize_rules_clean_up_speakers_and_lines() {
+    let f = Fixture::new();
+    f.line(1, 0x10, "main", Some(" 少女 "), "「ＡＢＣ、待って」");
+    let config = f.dir().join("project.toml");
+    std::fs::write(&config, r#"
+        [[normalize]]
+        name = "trim"
+        field = "speaker"
+        kind = "regex"
+        from = '^\s+|\s+$'
+        to = ""
+
+        [[normalize]]
+        kind = "unicode"
+        form = "nfkc"
+    "#).unwrap();
+
+    let mock = Mock::echo();
+    assert!(f.translate(&mock, &["-c", config.to_str().unwrap()]).status.success());
+    let c = &mock.completions()[0];
+    assert!(c.prompt.contains("[少女]: 「ABC、待って」"));
+    assert_eq!(c.prefix, "[Girl]: ");
+
+    // preflight counts speakers, however many lines they have
+    f.line(1, 0x20, "main", Some(" 少女 "), "「帰ろう」")
+        .line(1, 0x30, "main", Some("少女 "), "「うん」");
+    let out = f.run(&["-c", config.to_str().unwrap(), "preflight"]);
+    assert!(String::from_utf8_lossy(&out.stderr).contains("normalize trim fired on 2 speakers"));
+}
+
+#[test]
+fn exports_import_back_as_they_were() {
+    let f = Fixture::new();
+    f.variant(1, 0x10, "main", Some("少女"), "「待って」", Some("「待ってよ」"))
+        .line(1, 0x20, "main", None, "誰もいない。")
+        .line(1, 0x30, "main", None, "雨が降っていた。");
+    assert!(f.translate(&Mock::echo(), &[]).status.success());
+    f.conn().execute("UPDATE dialogueTl SET reviewed = 1 WHERE address = 32", ()).unwrap();
+    let translated = f.translations();
+    let reviewed = |f: &Fixture| -> Vec<u32> {
+        let conn = f.conn();
+        let mut stmt = conn.prepare("SELECT address FROM dialogueTl WHERE reviewed ORDER BY address").unwrap();
+        stmt.query_map((), |row| row.get(0)).unwrap().collect::<Result<_, _>>().unwrap()
+    };
+    let meta = |f: &Fixture| -> usize {
+        f.conn().query_row("SELECT count(*) FROM dialogueTlMeta", (), |row| row.get(0)).unwrap()
+    };
+
+    let export = f.dir().join("export.jsonl");
+    assert!(f.run(&["export", "-o", export.to_str().unwrap()]).status.success());
+    // over the same translations, nothing changes
+    assert!(f.run(&["import", export.to_str().unwrap()]).status.success());
+    assert_eq!(f.translations(), translated);
+    assert_eq!(reviewed(&f), [0x20]);
+    assert_eq!(meta(&f), 3);
+    // a new text leaves behind what was recorded about the old
+    let changed = f.dir().join("changed.jsonl");
+    std::fs::write(&changed, "{\"scriptid\": 1, \"address\": 48, \"tl_body\": \"It rained.\"}\n").unwrap();
+    assert!(f.run(&["import", changed.to_str().unwrap()]).status.success());
+    assert_eq!(meta(&f), 2);
+
+    f.conn().execute("DELETE FROM dialogueTl", ()).unwrap();
+    assert!(f.run(&["import", export.to_str().unwrap()]).status.success());
+    assert_eq!(f.translations(), translated);
+    assert_eq!(reviewed(&f), [0x20]);
+
+    // and the other way round
+    let again = f.dir().join("again.jsonl");
+    assert!(f.run(&["export", "-o", again.to_str().unwrap()]).status.success());
+    assert_eq!(std::fs::read_to_string(&again).unwrap(), std::fs::read_to_string(&export).unwrap());
+}